@@ -0,0 +1,212 @@
+// Администрирование: изменение политики начисления на ходу через API и
+// перечитывание файла конфигурации по SIGHUP. Каждое изменение пишется в журнал аудита.
+use crate::api_keys::Scope;
+use crate::auth::{self, AuthUser, CredentialsUnchecked};
+use crate::config::{ActivePolicy, ConfigLayer, LiveSettings, NodeConfig, Settings};
+use crate::error::ApiError;
+use crate::reward::{EarningMode, Rounding};
+use crate::store::PointsStore;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+
+pub const SOURCE_ADMIN_API: &str = "admin_api";
+pub const SOURCE_SIGHUP: &str = "sighup";
+
+/// Токен администратора (переменная ADMIN_TOKEN). Без него admin API доступен
+/// только администраторам — по сессии или по ключу с правом admin.
+pub struct AdminToken(pub Option<String>);
+
+// Пускает администратора, вошедшего через middleware, или `Authorization: Bearer <ADMIN_TOKEN>`
+fn authorize(req: &HttpRequest, admin: &AdminToken) -> Result<(), ApiError> {
+    let user = req.extensions().get::<AuthUser>().cloned();
+    if user.as_ref().is_some_and(|user| user.has_scope(Scope::Admin)) {
+        return Ok(());
+    }
+    let presented = req
+        .headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match (admin.0.as_deref(), presented) {
+        (Some(expected), Some(presented)) if bool::from(expected.as_bytes().ct_eq(presented.as_bytes())) => Ok(()),
+        _ if user.is_some() => Err(ApiError::Forbidden("admin rights required")),
+        _ if req.extensions().get::<CredentialsUnchecked>().is_some() => Err(auth::unauthenticated(req)),
+        _ => Err(ApiError::Unauthorized("admin credentials required")),
+    }
+}
+
+/// Частичное изменение политики: незаданные поля остаются прежними.
+/// `threshold` задаёт порог обоих направлений, пороги по направлениям важнее него.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyUpdate {
+    threshold: Option<u64>,
+    upload_threshold: Option<u64>,
+    download_threshold: Option<u64>,
+    upload_rate: Option<f64>,
+    download_rate: Option<f64>,
+    cap: Option<i64>,
+    rounding: Option<Rounding>,
+    interval_secs: Option<u64>,
+    earning_mode: Option<EarningMode>,
+}
+
+impl PolicyUpdate {
+    fn apply(self, current: &ActivePolicy) -> ActivePolicy {
+        let mut policy = current.clone();
+        let reward = &mut policy.reward;
+        if let Some(threshold) = self.threshold {
+            reward.upload.threshold = threshold;
+            reward.download.threshold = threshold;
+        }
+        reward.upload.threshold = self.upload_threshold.unwrap_or(reward.upload.threshold);
+        reward.download.threshold = self.download_threshold.unwrap_or(reward.download.threshold);
+        reward.upload.rate = self.upload_rate.unwrap_or(reward.upload.rate);
+        reward.download.rate = self.download_rate.unwrap_or(reward.download.rate);
+        reward.cap = self.cap.unwrap_or(reward.cap);
+        reward.rounding = self.rounding.unwrap_or(reward.rounding);
+        reward.interval = self.interval_secs.map_or(reward.interval, Duration::from_secs);
+        policy.earning_mode = self.earning_mode.unwrap_or(policy.earning_mode);
+        policy
+    }
+}
+
+// Изменения настроек идут строго по одному: снимок, аудит и замена выполняются
+// под этой блокировкой, иначе параллельный PATCH или SIGHUP затрёт чужое изменение.
+static SETTINGS_CHANGES: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Строит новые настройки из действующих и заменяет их, предварительно записав
+/// изменение целиком в журнал аудита. Возвращает прежние и новые настройки или `None`,
+/// если ничего не изменилось. Мониторы подхватят их в начале следующего интервала.
+pub async fn replace_settings(
+    store: &dyn PointsStore,
+    config: &Mutex<NodeConfig>,
+    source: &str,
+    change: impl FnOnce(&LiveSettings) -> Result<LiveSettings, ApiError>,
+) -> Result<Option<(LiveSettings, LiveSettings)>, ApiError> {
+    let _serialized = SETTINGS_CHANGES.lock().await;
+    let (node_id, previous) = {
+        let config = config.lock().unwrap();
+        (config.node_id.clone(), config.live_settings())
+    };
+    let settings = change(&previous)?;
+    let previous_json = serde_json::to_value(&previous).unwrap_or_default();
+    let current_json = serde_json::to_value(&settings).unwrap_or_default();
+    if previous_json == current_json {
+        return Ok(None);
+    }
+
+    store.record_config_change(source, &node_id, &previous_json, &current_json).await?;
+    config.lock().unwrap().apply(settings.clone());
+    tracing::info!(source, previous = %previous_json, current = %current_json, "Node settings changed");
+    Ok(Some((previous, settings)))
+}
+
+// Текущая политика начисления
+pub async fn get_policy(
+    req: HttpRequest,
+    admin: web::Data<AdminToken>,
+    config: web::Data<Arc<Mutex<NodeConfig>>>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&req, &admin)?;
+    Ok(HttpResponse::Ok().json(config.lock().unwrap().policy()))
+}
+
+// Изменение политики на ходу; действует со следующего интервала
+pub async fn update_policy(
+    req: HttpRequest,
+    admin: web::Data<AdminToken>,
+    store: web::Data<Arc<dyn PointsStore>>,
+    config: web::Data<Arc<Mutex<NodeConfig>>>,
+    update: web::Json<PolicyUpdate>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&req, &admin)?;
+
+    let update = update.into_inner();
+    let changed = replace_settings(store.get_ref().as_ref(), &config, SOURCE_ADMIN_API, |current| {
+        let policy = update.apply(&current.policy);
+        policy.reward.validate().map_err(|e| ApiError::bad_request(e.to_string()))?;
+        Ok(LiveSettings { policy, ..current.clone() })
+    })
+    .await?;
+    let (previous, current) = match changed {
+        Some((previous, current)) => (Some(previous.policy), current.policy),
+        None => (None, config.lock().unwrap().policy()),
+    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "changed": previous.is_some(),
+        "previous": previous,
+        "current": current,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    limit: Option<i64>,
+}
+
+// Журнал изменений политики, от новых к старым
+pub async fn get_audit_log(
+    req: HttpRequest,
+    admin: web::Data<AdminToken>,
+    store: web::Data<Arc<dyn PointsStore>>,
+    query: web::Query<AuditQuery>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&req, &admin)?;
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    Ok(HttpResponse::Ok().json(store.list_config_changes(limit).await?))
+}
+
+/// Перечитывает файл конфигурации по SIGHUP. Флаги и окружение по-прежнему
+/// важнее файла. На ходу меняются политика начисления, фильтр интерфейсов и
+/// ёмкости линков; адрес, порт, база и id ноды требуют перезапуска.
+/// Некорректный файл отклоняется целиком, действующие настройки не меняются.
+#[cfg(unix)]
+pub async fn reload_on_sighup(
+    store: Arc<dyn PointsStore>,
+    config: Arc<Mutex<NodeConfig>>,
+    path: Option<PathBuf>,
+    cli: ConfigLayer,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::warn!(error = %e, "Cannot listen for SIGHUP, config reload is disabled");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        let settings = match Settings::load(path.as_deref(), cli.clone()) {
+            Ok(settings) => settings,
+            Err(e) => {
+                tracing::warn!(error = %e, "Config reload rejected, keeping current settings");
+                continue;
+            }
+        };
+        let reloaded = LiveSettings {
+            policy: ActivePolicy { reward: settings.reward, earning_mode: settings.earning_mode },
+            interfaces: settings.interfaces,
+            capacities: settings.capacities,
+        };
+        match replace_settings(store.as_ref(), &config, SOURCE_SIGHUP, |_| Ok(reloaded)).await {
+            Ok(Some(_)) => {}
+            Ok(None) => tracing::info!("Config reloaded, nothing changed"),
+            Err(e) => tracing::error!(error = %e, "Failed to record reloaded config, keeping the current settings"),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_sighup(
+    _store: Arc<dyn PointsStore>,
+    _config: Arc<Mutex<NodeConfig>>,
+    _path: Option<PathBuf>,
+    _cli: ConfigLayer,
+) {
+}
@@ -0,0 +1,165 @@
+// Режим агента: только измеряет трафик и отправляет подписанные отчёты серверу,
+// доступа к базе данных у агента нет
+use crate::network::{InterfaceFilter, LinkCapacities, Meter, NetworkUsage};
+use crate::report::{self, EnrollRequest, NodeCredentials, UsageReport};
+use crate::store::unix_now;
+use reqwest::StatusCode;
+use std::path::PathBuf;
+use std::time::Duration;
+use sysinfo::Networks;
+use tokio::time::sleep;
+
+pub struct AgentOptions {
+    pub server: String,
+    pub node_id: String,
+    pub enrollment_token: Option<String>,
+    /// Ключ владельца ноды с правом usage:submit; нужен только для регистрации
+    pub api_key: Option<String>,
+    pub credentials_path: PathBuf,
+    pub interfaces: InterfaceFilter,
+    /// Пропускная способность интерфейсов; сервер по ней считает простаивающую ёмкость
+    pub capacities: LinkCapacities,
+    pub interval: Duration,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AgentError {
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("credentials file error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid credentials file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("node is not enrolled: an enrollment token and the owner's api key are required")]
+    NotEnrolled,
+    #[error("server rejected the request ({0}): {1}")]
+    Rejected(StatusCode, String),
+}
+
+fn endpoint(server: &str, path: &str) -> String {
+    format!("{}{}", server.trim_end_matches('/'), path)
+}
+
+// Загружает учётные данные ноды или регистрирует её на сервере
+async fn load_or_enroll(client: &reqwest::Client, options: &AgentOptions) -> Result<NodeCredentials, AgentError> {
+    if options.credentials_path.exists() {
+        let credentials: NodeCredentials = serde_json::from_slice(&std::fs::read(&options.credentials_path)?)?;
+        return Ok(credentials);
+    }
+
+    let (Some(token), Some(api_key)) = (options.enrollment_token.clone(), options.api_key.as_deref()) else {
+        return Err(AgentError::NotEnrolled);
+    };
+    // Нода регистрируется за владельцем ключа
+    let response = client
+        .post(endpoint(&options.server, "/nodes/enroll"))
+        .bearer_auth(api_key)
+        .json(&EnrollRequest { node_id: options.node_id.clone(), username: None, token })
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(AgentError::Rejected(response.status(), response.text().await?));
+    }
+    let credentials: NodeCredentials = response.json().await?;
+    save_credentials(&options.credentials_path, &credentials)?;
+    tracing::info!(
+        node = %credentials.node_id,
+        credentials = %options.credentials_path.display(),
+        "Enrolled node, credentials saved"
+    );
+    Ok(credentials)
+}
+
+fn save_credentials(path: &PathBuf, credentials: &NodeCredentials) -> Result<(), AgentError> {
+    let data = serde_json::to_vec_pretty(credentials)?;
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        // Секрет ноды читает только владелец файла
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(&data)?;
+    }
+    #[cfg(not(unix))]
+    std::fs::write(path, data)?;
+    Ok(())
+}
+
+async fn send_report(
+    client: &reqwest::Client,
+    server: &str,
+    credentials: &NodeCredentials,
+    usage_report: &UsageReport,
+) -> Result<serde_json::Value, AgentError> {
+    let body = serde_json::to_vec(usage_report)?;
+    let timestamp = unix_now();
+    let response = client
+        .post(endpoint(server, &format!("/nodes/{}/reports", credentials.node_id)))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(report::NODE_ID_HEADER, &credentials.node_id)
+        .header(report::TIMESTAMP_HEADER, timestamp.to_string())
+        .header(report::SIGNATURE_HEADER, report::sign(&credentials.secret, timestamp, &body))
+        .body(body)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(AgentError::Rejected(response.status(), response.text().await?));
+    }
+    Ok(response.json().await?)
+}
+
+/// Основной цикл агента. Пока сервер недоступен, трафик копится и уходит
+/// одним отчётом за весь пропущенный период.
+pub async fn run(options: AgentOptions) -> Result<(), AgentError> {
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?;
+    let credentials = load_or_enroll(&client, &options).await?;
+
+    let mut networks = Networks::new_with_refreshed_list();
+    let mut meter = Meter::new(&NetworkUsage::new(&networks, &options.interfaces));
+    let mut pending = NetworkUsage::default();
+    let mut interval_start = unix_now();
+
+    loop {
+        sleep(options.interval).await;
+
+        networks.refresh(true);
+        pending.accumulate(&meter.advance(&NetworkUsage::new(&networks, &options.interfaces)));
+        let usage_report = UsageReport {
+            interval_start,
+            interval_end: unix_now(),
+            bytes_sent: pending.sent(),
+            bytes_received: pending.received(),
+            interfaces: pending.interfaces(&options.capacities),
+        };
+
+        match send_report(&client, &options.server, &credentials, &usage_report).await {
+            Ok(reply) => tracing::info!(
+                node = %credentials.node_id,
+                sent = usage_report.bytes_sent,
+                received = usage_report.bytes_received,
+                reply = %reply,
+                "Report accepted"
+            ),
+            // Сервер уже принял более поздний интервал — этот отбрасываем
+            Err(AgentError::Rejected(StatusCode::CONFLICT, message)) => {
+                tracing::warn!(reason = %message, "Report dropped by server")
+            }
+            Err(AgentError::Rejected(status, message))
+                if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN =>
+            {
+                return Err(AgentError::Rejected(status, message));
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to send report, will retry with the next interval");
+                continue;
+            }
+        }
+
+        pending = NetworkUsage::default();
+        interval_start = usage_report.interval_end;
+    }
+}
@@ -0,0 +1,239 @@
+// API-ключи для скриптов и нод-агентов. Ключ показывается один раз при создании,
+// в базе хранится только его SHA-256. Что можно делать с ключом, задают его права (scope).
+use crate::auth::{self, AuthUser};
+use crate::error::ApiError;
+use crate::store::{NewApiKey, PointsStore, StoreError};
+use actix_web::{web, HttpResponse};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Начало каждого ключа: по нему ключ отличается от токена сессии.
+pub const KEY_PREFIX: &str = "nfk_";
+
+/// Сколько первых символов ключа хранится открыто, чтобы его можно было узнать в списке.
+const SHOWN_PREFIX_LEN: usize = 12;
+
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum Scope {
+    /// Чтение статистики
+    #[serde(rename = "stats:read")]
+    #[value(name = "stats:read")]
+    StatsRead,
+    /// Отправка отчётов о трафике от имени ноды
+    #[serde(rename = "usage:submit")]
+    #[value(name = "usage:submit")]
+    UsageSubmit,
+    /// Admin API; только для ключей администраторов
+    #[serde(rename = "admin")]
+    #[value(name = "admin")]
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::StatsRead => "stats:read",
+            Scope::UsageSubmit => "usage:submit",
+            Scope::Admin => "admin",
+        }
+    }
+
+    /// Разбирает права, сохранённые в базе. Незнакомые значения пропускаются.
+    pub fn parse_list(scopes: &[String]) -> Vec<Scope> {
+        scopes
+            .iter()
+            .filter_map(|scope| Scope::from_str(scope, false).ok())
+            .collect()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyError {
+    #[error(transparent)]
+    Store(#[from] StoreError),
+    #[error("{0}")]
+    Invalid(&'static str),
+}
+
+impl From<KeyError> for ApiError {
+    fn from(e: KeyError) -> Self {
+        match e {
+            KeyError::Store(e) => e.into(),
+            KeyError::Invalid(message) => ApiError::bad_request(message),
+        }
+    }
+}
+
+/// Выпущенный ключ. Поле `key` больше нигде не сохраняется.
+#[derive(Debug, Serialize)]
+pub struct IssuedKey {
+    pub id: i64,
+    pub key: String,
+    pub prefix: String,
+    pub name: String,
+    pub username: String,
+    pub node_id: Option<String>,
+    pub scopes: Vec<Scope>,
+}
+
+/// Выпускает ключ пользователю. Ключ ноды действует только для неё, и нода
+/// должна принадлежать тому же пользователю; право admin получают только администраторы.
+pub async fn issue(
+    store: &dyn PointsStore,
+    username: &str,
+    node_id: Option<&str>,
+    name: &str,
+    scopes: &[Scope],
+) -> Result<IssuedKey, KeyError> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(KeyError::Invalid("key name must be 1 to 64 characters long"));
+    }
+    let scopes = scopes.iter().fold(Vec::new(), |mut unique, scope| {
+        if !unique.contains(scope) {
+            unique.push(*scope);
+        }
+        unique
+    });
+    if scopes.is_empty() {
+        return Err(KeyError::Invalid("at least one scope is required"));
+    }
+
+    let Some(owner) = store.get_credentials(username).await? else {
+        return Err(StoreError::UserNotFound(username.to_string()).into());
+    };
+    if scopes.contains(&Scope::Admin) && !owner.is_admin {
+        return Err(KeyError::Invalid("only administrators can hold keys with the admin scope"));
+    }
+    if let Some(node_id) = node_id {
+        match store.get_node(node_id).await? {
+            Some(node) if node.username == owner.username => {}
+            _ => return Err(KeyError::Invalid("node not found or owned by another user")),
+        }
+    }
+
+    let key = format!("{}{}", KEY_PREFIX, auth::generate_token());
+    let prefix = key[..SHOWN_PREFIX_LEN].to_string();
+    let new_key = NewApiKey {
+        username: owner.username.clone(),
+        node_id: node_id.map(str::to_string),
+        name: name.to_string(),
+        prefix: prefix.clone(),
+        key_hash: auth::hash_token(&key),
+        scopes: scopes.iter().map(Scope::as_str).collect::<Vec<_>>().join(" "),
+    };
+    let id = store.create_api_key(&new_key).await?;
+    Ok(IssuedKey {
+        id,
+        key,
+        prefix,
+        name: new_key.name,
+        username: new_key.username,
+        node_id: new_key.node_id,
+        scopes,
+    })
+}
+
+// Чужими ключами распоряжается только администратор
+fn owner_of(user: &AuthUser, username: Option<String>) -> Result<String, ApiError> {
+    if !user.can_manage_keys() {
+        return Err(ApiError::Forbidden("api keys can only be managed from a session or an admin key"));
+    }
+    match username {
+        Some(username) if !user.can_access(&username) => Err(auth::forbidden()),
+        Some(username) => Ok(username),
+        None => Ok(user.username.clone()),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateKeyRequest {
+    name: String,
+    scopes: Vec<Scope>,
+    node_id: Option<String>,
+    /// Владелец ключа, если это не сам вызывающий (только для администраторов)
+    username: Option<String>,
+}
+
+// Выпуск ключа; сам ключ возвращается только в этом ответе
+pub async fn create_key(
+    user: AuthUser,
+    store: web::Data<Arc<dyn PointsStore>>,
+    request: web::Json<CreateKeyRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let CreateKeyRequest { name, scopes, node_id, username } = request.into_inner();
+    let username = owner_of(&user, username)?;
+    let issued = issue(store.get_ref().as_ref(), &username, node_id.as_deref(), &name, &scopes).await?;
+    tracing::info!(prefix = %issued.prefix, name = %issued.name, user = %issued.username, "Issued api key");
+    Ok(HttpResponse::Created().json(issued))
+}
+
+#[derive(Deserialize)]
+pub struct KeysQuery {
+    username: Option<String>,
+}
+
+// Ключи пользователя без самих ключей
+pub async fn list_keys(
+    user: AuthUser,
+    store: web::Data<Arc<dyn PointsStore>>,
+    query: web::Query<KeysQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let username = owner_of(&user, query.into_inner().username)?;
+    Ok(HttpResponse::Ok().json(store.list_api_keys(Some(&username)).await?))
+}
+
+// Отзыв ключа; администратор может отозвать любой
+pub async fn revoke_key(
+    user: AuthUser,
+    store: web::Data<Arc<dyn PointsStore>>,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    owner_of(&user, None)?;
+    let owner = (!user.has_scope(Scope::Admin)).then_some(user.username.as_str());
+    if !store.revoke_api_key(*id, owner).await? {
+        return Err(ApiError::NotFound("api key not found or already revoked"));
+    }
+    tracing::info!(id = *id, by = %user.username, "Revoked api key");
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{self, migrations, Enrollment, PoolOptions};
+
+    #[tokio::test]
+    async fn keys_are_issued_within_the_owners_rights() {
+        let store = store::connect("sqlite::memory:", &PoolOptions::default()).await.unwrap();
+        let store = store.as_ref();
+        migrations::run(store, false).await.unwrap();
+        for name in ["alice", "bob", "root"] {
+            store.add_user(name, 0).await.unwrap();
+        }
+        store.set_user_admin("root", true).await.unwrap();
+        assert_eq!(store.create_node("n1", "alice", "secret").await.unwrap(), Enrollment::Created);
+        assert_eq!(store.create_node("n2", "bob", "secret").await.unwrap(), Enrollment::Created);
+
+        let admin = issue(store, "root", None, "ops", &[Scope::Admin, Scope::Admin]).await.unwrap();
+        assert_eq!(admin.scopes, [Scope::Admin]);
+        assert!(admin.key.starts_with(&admin.prefix) && admin.prefix.starts_with(KEY_PREFIX));
+        let denied = issue(store, "alice", None, "ops", &[Scope::StatsRead, Scope::Admin]).await;
+        assert!(matches!(denied, Err(KeyError::Invalid(_))));
+
+        let agent = issue(store, "alice", Some("n1"), "agent", &[Scope::UsageSubmit]).await.unwrap();
+        assert_eq!(agent.node_id.as_deref(), Some("n1"));
+        for node in ["n2", "n3"] {
+            let denied = issue(store, "alice", Some(node), "agent", &[Scope::UsageSubmit]).await;
+            assert!(matches!(denied, Err(KeyError::Invalid(_))));
+        }
+        assert!(matches!(issue(store, "alice", None, " ", &[Scope::StatsRead]).await, Err(KeyError::Invalid(_))));
+        assert!(matches!(issue(store, "alice", None, "empty", &[]).await, Err(KeyError::Invalid(_))));
+        let unknown = issue(store, "nobody", None, "key", &[Scope::StatsRead]).await;
+        assert!(matches!(unknown, Err(KeyError::Store(StoreError::UserNotFound(_)))));
+    }
+}
@@ -0,0 +1,406 @@
+// Регистрация и вход пользователей веб-API. Пароли хранятся в argon2, сессия —
+// случайный токен, который клиент передаёт в `Authorization: Bearer` или в cookie.
+// В базе лежит только SHA-256 токена, так что утечка таблицы сессий не даёт войти.
+// Тем же заголовком передаются API-ключи; middleware `identify` узнаёт по токену
+// пользователя и кладёт его в расширения запроса.
+use crate::api_keys::{self, Scope};
+use crate::error::ApiError;
+use crate::store::{self, Node, PointsStore};
+use actix_web::body::MessageBody;
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse, Responder};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, OnceLock};
+
+pub const SESSION_COOKIE: &str = "nf_session";
+
+/// Срок жизни сессии, секунд.
+const SESSION_TTL_SECS: i64 = 30 * 24 * 3600;
+
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_PASSWORD_LEN: usize = 1024;
+
+/// Имя пользователя: 3–32 символа из латиницы, цифр, `_`, `-` и `.`.
+pub fn validate_username(username: &str) -> Result<(), &'static str> {
+    if !(3..=32).contains(&username.len()) {
+        return Err("username must be 3 to 32 characters long");
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+        return Err("username may contain only latin letters, digits, '_', '-' and '.'");
+    }
+    Ok(())
+}
+
+pub fn validate_password(password: &str) -> Result<(), &'static str> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err("password must be at least 8 characters long");
+    }
+    if password.len() > MAX_PASSWORD_LEN {
+        return Err("password is too long");
+    }
+    Ok(())
+}
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+/// Проверяет пароль. Для аккаунта без пароля (или несуществующего) всё равно
+/// считает хеш, чтобы по времени ответа нельзя было узнать, есть ли такое имя.
+pub fn verify_password(password: &str, hash: Option<&str>) -> bool {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    let dummy = DUMMY_HASH.get_or_init(|| hash_password("dummy password").unwrap_or_default());
+    let (hash, known) = match hash {
+        Some(hash) => (hash, true),
+        None => (dummy.as_str(), false),
+    };
+    let verified = PasswordHash::new(hash)
+        .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        .unwrap_or(false);
+    verified && known
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub(crate) fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+// Токен из заголовка Authorization имеет приоритет над cookie
+fn session_token(req: &HttpRequest) -> Option<String> {
+    let bearer = req
+        .headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::to_string);
+    bearer.or_else(|| req.cookie(SESSION_COOKIE).map(|c| c.value().to_string()))
+}
+
+/// Ключ, которым подписан запрос, и его права.
+#[derive(Debug, Clone)]
+pub struct KeyGrant {
+    pub id: i64,
+    pub node_id: Option<String>,
+    pub scopes: Vec<Scope>,
+}
+
+/// Пользователь, вошедший в систему по сессии или API-ключу. Обработчик с таким
+/// аргументом отвечает 401 на запросы без действующих учётных данных.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub username: String,
+    pub is_admin: bool,
+    /// `None` для сессии: у неё все права пользователя, кроме отправки отчётов
+    pub key: Option<KeyGrant>,
+}
+
+impl AuthUser {
+    /// Право admin действует только у администратора, даже если оно есть у ключа.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        let granted = match &self.key {
+            Some(key) => key.scopes.contains(&scope),
+            None => scope != Scope::UsageSubmit,
+        };
+        granted && (scope != Scope::Admin || self.is_admin)
+    }
+
+    /// Пользователь видит только свои данные, администратор — данные всех.
+    pub fn can_access(&self, username: &str) -> bool {
+        self.has_scope(Scope::StatsRead) && (self.username == username || self.has_scope(Scope::Admin))
+    }
+
+    /// Ключами управляют из сессии; ключ может выпускать ключи, только если у него есть право admin.
+    pub fn can_manage_keys(&self) -> bool {
+        self.key.is_none() || self.has_scope(Scope::Admin)
+    }
+
+    /// Ноду регистрируют за свой аккаунт из сессии или ключом с правом usage:submit
+    /// (ключ, выпущенный для ноды, годится только для неё); за чужой — только администратор.
+    pub fn can_enroll(&self, username: &str, node_id: &str) -> bool {
+        let allowed = match &self.key {
+            Some(key) => {
+                key.scopes.contains(&Scope::UsageSubmit) && key.node_id.as_deref().is_none_or(|id| id == node_id)
+            }
+            None => true,
+        };
+        allowed && (self.username == username || self.has_scope(Scope::Admin))
+    }
+
+    /// Отчёт за ноду можно отправить ключом её владельца с правом usage:submit;
+    /// ключ, выпущенный для ноды, годится только для неё.
+    pub fn can_submit_for(&self, node: &Node) -> bool {
+        let bound_to_node = match self.key.as_ref().and_then(|key| key.node_id.as_deref()) {
+            Some(node_id) => node_id == node.id,
+            None => true,
+        };
+        self.has_scope(Scope::UsageSubmit) && self.username == node.username && bound_to_node
+    }
+}
+
+// Находит владельца сессии или API-ключа. Ключи и сессии отключённых пользователей не действуют.
+async fn resolve(store: &dyn PointsStore, token: &str) -> Result<Option<AuthUser>, store::StoreError> {
+    if token.starts_with(api_keys::KEY_PREFIX) {
+        let owner = store.authenticate_api_key(&hash_token(token)).await?;
+        return Ok(owner.filter(|owner| owner.enabled).map(|owner| AuthUser {
+            username: owner.key.username,
+            is_admin: owner.is_admin,
+            key: Some(KeyGrant {
+                id: owner.key.id,
+                node_id: owner.key.node_id,
+                scopes: Scope::parse_list(&owner.key.scopes),
+            }),
+        }));
+    }
+    let session = store.get_session(&hash_token(token)).await?;
+    Ok(session.map(|user| AuthUser { username: user.username, is_admin: user.is_admin, key: None }))
+}
+
+/// Токен был, но проверить его не удалось: база недоступна. Обработчики, которым
+/// нужен пользователь, отвечают 503, а не 401, остальные (`/healthz`, `/metrics`) работают.
+#[derive(Debug, Clone, Copy)]
+pub struct CredentialsUnchecked;
+
+/// Middleware перед всеми маршрутами: по токену из заголовка или cookie находит
+/// пользователя и кладёт `AuthUser` в расширения запроса. Запрос без учётных данных
+/// (или с чужим токеном, например ADMIN_TOKEN) проходит дальше как анонимный —
+/// отказывают уже обработчики. Сбой базы тоже не останавливает запрос.
+pub async fn identify(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let token = session_token(req.request());
+    let store = req.app_data::<web::Data<Arc<dyn PointsStore>>>().cloned();
+    if let (Some(token), Some(store)) = (token, store) {
+        match resolve(store.get_ref().as_ref(), &token).await {
+            Ok(Some(user)) => {
+                req.extensions_mut().insert(user);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!(error = %e, "Cannot check credentials, continuing as anonymous");
+                req.extensions_mut().insert(CredentialsUnchecked);
+            }
+        }
+    }
+    next.call(req).await
+}
+
+/// Почему у запроса нет пользователя.
+pub fn unauthenticated(req: &HttpRequest) -> ApiError {
+    if req.extensions().get::<CredentialsUnchecked>().is_some() {
+        return ApiError::Unavailable("cannot check credentials right now, try again later");
+    }
+    match session_token(req) {
+        Some(_) => ApiError::Unauthorized("invalid or expired credentials"),
+        None => ApiError::Unauthorized("authentication required"),
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = ApiError;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user = req.extensions().get::<AuthUser>().cloned();
+        std::future::ready(user.ok_or_else(|| unauthenticated(req)))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CredentialsRequest {
+    username: String,
+    password: String,
+}
+
+// Открывает сессию и отдаёт токен в теле ответа и в cookie
+async fn start_session(
+    store: &dyn PointsStore,
+    username: &str,
+    is_admin: bool,
+    status: StatusCode,
+) -> Result<HttpResponse, ApiError> {
+    let token = generate_token();
+    let expires_at = store::unix_now() + SESSION_TTL_SECS;
+    store.create_session(username, &hash_token(&token), expires_at).await?;
+    let cookie = Cookie::build(SESSION_COOKIE, token.clone())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .max_age(time::Duration::seconds(SESSION_TTL_SECS))
+        .finish();
+    Ok(HttpResponse::build(status).cookie(cookie).json(serde_json::json!({
+        "username": username,
+        "is_admin": is_admin,
+        "token": token,
+        "expires_at": expires_at,
+    })))
+}
+
+// Регистрация: создаёт аккаунт с паролем и сразу открывает сессию.
+// Аккаунт создаётся без ноды и ничего не зарабатывает, пока ноду не зарегистрируют
+// (POST /nodes/enroll) или не привяжет оператор (`user bind`)
+pub async fn signup(
+    store: web::Data<Arc<dyn PointsStore>>,
+    request: web::Json<CredentialsRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let CredentialsRequest { username, password } = request.into_inner();
+    validate_username(&username).map_err(ApiError::InvalidUsername)?;
+    validate_password(&password).map_err(ApiError::bad_request)?;
+
+    // argon2 намеренно медленный — считаем его вне потока обработки запросов
+    let hash = web::block(move || hash_password(&password))
+        .await
+        .map_err(|e| ApiError::Internal(e.to_string()))?
+        .map_err(|e| ApiError::Internal(format!("failed to hash password: {}", e)))?;
+    if !store.create_account(&username, &hash).await? {
+        return Err(ApiError::Conflict("username is already taken"));
+    }
+    tracing::info!(user = %username, "Signed up user");
+    start_session(store.get_ref().as_ref(), &username, false, StatusCode::CREATED).await
+}
+
+// Вход по имени и паролю
+pub async fn login(
+    store: web::Data<Arc<dyn PointsStore>>,
+    request: web::Json<CredentialsRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let CredentialsRequest { username, password } = request.into_inner();
+    let credentials = store.get_credentials(&username).await?;
+    let hash = credentials.as_ref().and_then(|c| c.password_hash.clone());
+    let verified = web::block(move || verify_password(&password, hash.as_deref())).await.unwrap_or(false);
+    match credentials {
+        Some(credentials) if verified && !credentials.enabled => Err(ApiError::Forbidden("account is disabled")),
+        Some(credentials) if verified => {
+            start_session(store.get_ref().as_ref(), &credentials.username, credentials.is_admin, StatusCode::OK).await
+        }
+        _ => Err(ApiError::Unauthorized("invalid username or password")),
+    }
+}
+
+// Выход: сессия удаляется из базы, cookie стирается
+pub async fn logout(req: HttpRequest, store: web::Data<Arc<dyn PointsStore>>) -> Result<HttpResponse, ApiError> {
+    if let Some(token) = session_token(&req) {
+        store.delete_session(&hash_token(&token)).await?;
+    }
+    let mut cookie = Cookie::build(SESSION_COOKIE, "").path("/").finish();
+    cookie.make_removal();
+    Ok(HttpResponse::NoContent().cookie(cookie).finish())
+}
+
+// Кто вошёл в систему; для API-ключа — ещё и его id, нода и права
+pub async fn me(user: AuthUser) -> impl Responder {
+    let mut body = serde_json::json!({ "username": user.username, "is_admin": user.is_admin });
+    if let Some(key) = &user.key {
+        body["key_id"] = key.id.into();
+        body["node_id"] = serde_json::json!(key.node_id);
+        body["scopes"] = serde_json::json!(key.scopes);
+    }
+    HttpResponse::Ok().json(body)
+}
+
+/// Ошибка 403 для чужих данных.
+pub fn forbidden() -> ApiError {
+    ApiError::Forbidden("you can only access your own data")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::PoolOptions;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{middleware, App};
+
+    fn session(username: &str, is_admin: bool) -> AuthUser {
+        AuthUser { username: username.to_string(), is_admin, key: None }
+    }
+
+    fn key(username: &str, is_admin: bool, node_id: Option<&str>, scopes: &[Scope]) -> AuthUser {
+        let grant = KeyGrant { id: 1, node_id: node_id.map(str::to_string), scopes: scopes.to_vec() };
+        AuthUser { username: username.to_string(), is_admin, key: Some(grant) }
+    }
+
+    fn node(id: &str, username: &str) -> Node {
+        Node {
+            id: id.to_string(),
+            username: username.to_string(),
+            user_enabled: true,
+            secret: String::new(),
+            enrolled_at: 0,
+        }
+    }
+
+    #[test]
+    fn scopes_depend_on_credentials() {
+        // Сессия не отправляет отчёты, а право admin есть только у администратора
+        assert!(session("alice", false).has_scope(Scope::StatsRead));
+        assert!(!session("alice", false).has_scope(Scope::UsageSubmit));
+        assert!(!session("alice", false).has_scope(Scope::Admin));
+        assert!(session("root", true).has_scope(Scope::Admin));
+        assert!(!key("alice", false, None, &[Scope::Admin]).has_scope(Scope::Admin));
+        assert!(!key("root", true, None, &[Scope::StatsRead]).has_scope(Scope::Admin));
+
+        assert!(session("alice", false).can_access("alice"));
+        assert!(!session("alice", false).can_access("bob"));
+        assert!(session("root", true).can_access("bob"));
+        assert!(!key("alice", false, None, &[Scope::UsageSubmit]).can_access("alice"));
+        assert!(!key("root", true, None, &[Scope::Admin]).can_access("bob"));
+        assert!(key("root", true, None, &[Scope::Admin, Scope::StatsRead]).can_access("bob"));
+    }
+
+    #[test]
+    fn enrollment_and_reports_are_limited_to_own_nodes() {
+        assert!(session("alice", false).can_enroll("alice", "n1"));
+        assert!(!session("alice", false).can_enroll("bob", "n1"));
+        assert!(session("root", true).can_enroll("bob", "n1"));
+        assert!(!key("alice", false, None, &[Scope::StatsRead]).can_enroll("alice", "n1"));
+        let bound = key("alice", false, Some("n1"), &[Scope::UsageSubmit]);
+        assert!(bound.can_enroll("alice", "n1"));
+        assert!(!bound.can_enroll("alice", "n2"));
+
+        assert!(bound.can_submit_for(&node("n1", "alice")));
+        assert!(!bound.can_submit_for(&node("n2", "alice")));
+        let any_node = key("alice", false, None, &[Scope::UsageSubmit]);
+        assert!(any_node.can_submit_for(&node("n2", "alice")));
+        assert!(!any_node.can_submit_for(&node("n3", "bob")));
+        assert!(!key("root", true, None, &[Scope::UsageSubmit, Scope::Admin]).can_submit_for(&node("n3", "bob")));
+        assert!(!session("alice", false).can_submit_for(&node("n1", "alice")));
+    }
+
+    async fn whoami(user: AuthUser) -> HttpResponse {
+        HttpResponse::Ok().body(user.username)
+    }
+
+    #[actix_web::test]
+    async fn store_failure_does_not_block_anonymous_routes() {
+        // Без миграций поиск сессии падает с ошибкой базы
+        let store = store::connect("sqlite::memory:", &PoolOptions::default()).await.unwrap();
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(store))
+                .wrap(middleware::from_fn(identify))
+                .route("/healthz", web::get().to(HttpResponse::Ok))
+                .route("/me", web::get().to(whoami)),
+        )
+        .await;
+        let get = |path: &str| TestRequest::get().uri(path).insert_header(("Authorization", "Bearer token"));
+
+        let health = call_service(&app, get("/healthz").to_request()).await;
+        assert_eq!(health.status(), StatusCode::OK);
+        let me = call_service(&app, get("/me").to_request()).await;
+        assert_eq!(me.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
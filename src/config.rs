@@ -0,0 +1,505 @@
+// Конфигурация ноды: слои настроек (файл, окружение, CLI) и итоговые настройки,
+// общие для монитора и HTTP-обработчиков.
+//
+// Порядок приоритета, от слабого к сильному:
+//   1. значения по умолчанию;
+//   2. TOML-файл из --config (или NETWORK_FARMING_CONFIG);
+//   3. переменные окружения (см. `ConfigLayer::from_env`);
+//   4. флаги командной строки.
+// Списки (маски интерфейсов, ёмкости линков) не объединяются: более сильный слой
+// заменяет список целиком. Пороги по направлениям важнее общего порога,
+// из какого бы слоя они ни пришли.
+use crate::history::Retention;
+use crate::logging::LogFormat;
+use crate::network::{CapacityError, FilterError, InterfaceFilter, LinkCapacities};
+use crate::reward::{DirectionRate, EarningMode, PolicyError, RewardPolicy, Rounding};
+use crate::store::PoolOptions;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use sysinfo::System;
+
+pub const CONFIG_PATH_ENV: &str = "NETWORK_FARMING_CONFIG";
+
+const LOG_LEVELS: &[&str] = &["error", "warn", "info", "debug", "trace"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("cannot read config file {path}: {source}")]
+    Read { path: PathBuf, source: std::io::Error },
+    #[error("invalid config file {path}: {source}")]
+    Parse { path: PathBuf, source: toml::de::Error },
+    #[error("invalid value '{value}' in environment variable {var}: {reason}")]
+    Env { var: &'static str, value: String, reason: String },
+    #[error("invalid reward policy: {0}")]
+    Policy(#[from] PolicyError),
+    #[error(transparent)]
+    Filter(#[from] FilterError),
+    #[error(transparent)]
+    Capacity(#[from] CapacityError),
+    #[error("invalid log level '{0}', expected one of: error, warn, info, debug, trace")]
+    LogLevel(String),
+    #[error("registry refresh interval must be at least 1 second")]
+    RegistryRefresh,
+    #[error("database pool size must be at least 1")]
+    PoolSize,
+    #[error("database connect timeout must be at least 1 second")]
+    ConnectTimeout,
+    #[error("retention {0} = {1} days is too long, at most {MAX_RETENTION_DAYS} days (or 0 to keep forever)")]
+    Retention(&'static str, u64),
+    #[error("database url is not set: use [database] url in the config file, DATABASE_URL or --database-url")]
+    MissingDatabaseUrl,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    /// Адрес, на котором слушает HTTP-сервер
+    pub bind: Option<String>,
+    pub port: Option<u16>,
+    /// Идентификатор ноды в журнале начислений (по умолчанию имя хоста)
+    pub node_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSection {
+    /// postgres://…, sqlite://путь или sqlite::memory:
+    pub url: Option<String>,
+    /// Размер пула соединений PostgreSQL
+    pub pool_size: Option<usize>,
+    pub connect_timeout_secs: Option<u64>,
+    /// Предел выполнения запроса в миллисекундах; 0 — без предела
+    pub statement_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MeteringSection {
+    pub include_interfaces: Option<Vec<String>>,
+    pub exclude_interfaces: Option<Vec<String>>,
+    /// Ёмкость линков в виде "eth0=1000" (Мбит/с)
+    pub link_capacities: Option<Vec<String>>,
+    pub earning_mode: Option<EarningMode>,
+    pub registry_refresh_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RewardSection {
+    /// Общий порог для обоих направлений
+    pub threshold: Option<u64>,
+    pub upload_threshold: Option<u64>,
+    pub download_threshold: Option<u64>,
+    pub upload_rate: Option<f64>,
+    pub download_rate: Option<f64>,
+    pub cap: Option<i64>,
+    pub rounding: Option<Rounding>,
+    pub interval_secs: Option<u64>,
+}
+
+/// Дольше ста лет срок хранения не задаётся: для этого есть 0.
+pub const MAX_RETENTION_DAYS: u64 = 36525;
+
+/// Сроки хранения истории трафика в сутках; 0 — хранить всегда.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionSection {
+    /// Исходные интервалы
+    pub samples_days: Option<u64>,
+    pub hourly_days: Option<u64>,
+    pub daily_days: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSection {
+    pub level: Option<String>,
+    /// text или json
+    pub format: Option<LogFormat>,
+}
+
+/// Один слой настроек: незаданные значения берутся из более слабых слоёв.
+/// В таком же виде читается файл конфигурации.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigLayer {
+    pub server: ServerSection,
+    pub database: DatabaseSection,
+    pub metering: MeteringSection,
+    pub reward: RewardSection,
+    pub retention: RetentionSection,
+    pub logging: LoggingSection,
+}
+
+fn parse_env<T: std::str::FromStr>(var: &'static str, value: Option<String>) -> Result<Option<T>, ConfigError>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .map(|value| {
+            value.parse().map_err(|e: T::Err| ConfigError::Env { var, reason: e.to_string(), value })
+        })
+        .transpose()
+}
+
+fn parse_env_enum<T: ValueEnum>(var: &'static str, value: Option<String>) -> Result<Option<T>, ConfigError> {
+    value
+        .map(|value| T::from_str(&value, true).map_err(|reason| ConfigError::Env { var, value, reason }))
+        .transpose()
+}
+
+impl ConfigLayer {
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Read { path: path.to_path_buf(), source })?;
+        toml::from_str(&text).map_err(|source| ConfigError::Parse { path: path.to_path_buf(), source })
+    }
+
+    /// Слой из переменных окружения:
+    ///
+    /// | переменная                                | настройка                         |
+    /// |-------------------------------------------|-----------------------------------|
+    /// | `NETWORK_FARMING_BIND`                    | `[server] bind`                   |
+    /// | `PORT`                                    | `[server] port`                   |
+    /// | `NETWORK_FARMING_NODE_ID`                 | `[server] node_id`                |
+    /// | `DATABASE_URL`                            | `[database] url`                  |
+    /// | `NETWORK_FARMING_DB_POOL_SIZE`            | `[database] pool_size`            |
+    /// | `NETWORK_FARMING_DB_STATEMENT_TIMEOUT_MS` | `[database] statement_timeout_ms` |
+    /// | `NETWORK_FARMING_EARNING_MODE`            | `[metering] earning_mode`         |
+    /// | `NETWORK_FARMING_THRESHOLD`               | `[reward] threshold`              |
+    /// | `NETWORK_FARMING_INTERVAL`                | `[reward] interval_secs`          |
+    /// | `NETWORK_FARMING_LOG_LEVEL`               | `[logging] level`                 |
+    /// | `NETWORK_FARMING_LOG_FORMAT`              | `[logging] format`                |
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        Ok(ConfigLayer {
+            server: ServerSection {
+                bind: var("NETWORK_FARMING_BIND"),
+                port: parse_env("PORT", var("PORT"))?,
+                node_id: var("NETWORK_FARMING_NODE_ID"),
+            },
+            database: DatabaseSection {
+                url: var("DATABASE_URL"),
+                pool_size: parse_env("NETWORK_FARMING_DB_POOL_SIZE", var("NETWORK_FARMING_DB_POOL_SIZE"))?,
+                connect_timeout_secs: None,
+                statement_timeout_ms: parse_env(
+                    "NETWORK_FARMING_DB_STATEMENT_TIMEOUT_MS",
+                    var("NETWORK_FARMING_DB_STATEMENT_TIMEOUT_MS"),
+                )?,
+            },
+            metering: MeteringSection {
+                earning_mode: parse_env_enum("NETWORK_FARMING_EARNING_MODE", var("NETWORK_FARMING_EARNING_MODE"))?,
+                ..Default::default()
+            },
+            reward: RewardSection {
+                threshold: parse_env("NETWORK_FARMING_THRESHOLD", var("NETWORK_FARMING_THRESHOLD"))?,
+                interval_secs: parse_env("NETWORK_FARMING_INTERVAL", var("NETWORK_FARMING_INTERVAL"))?,
+                ..Default::default()
+            },
+            retention: RetentionSection::default(),
+            logging: LoggingSection {
+                level: var("NETWORK_FARMING_LOG_LEVEL"),
+                format: parse_env_enum("NETWORK_FARMING_LOG_FORMAT", var("NETWORK_FARMING_LOG_FORMAT"))?,
+            },
+        })
+    }
+
+    /// Накладывает более сильный слой поверх этого.
+    pub fn merge(self, over: ConfigLayer) -> ConfigLayer {
+        ConfigLayer {
+            server: ServerSection {
+                bind: over.server.bind.or(self.server.bind),
+                port: over.server.port.or(self.server.port),
+                node_id: over.server.node_id.or(self.server.node_id),
+            },
+            database: DatabaseSection {
+                url: over.database.url.or(self.database.url),
+                pool_size: over.database.pool_size.or(self.database.pool_size),
+                connect_timeout_secs: over.database.connect_timeout_secs.or(self.database.connect_timeout_secs),
+                statement_timeout_ms: over.database.statement_timeout_ms.or(self.database.statement_timeout_ms),
+            },
+            metering: MeteringSection {
+                include_interfaces: over.metering.include_interfaces.or(self.metering.include_interfaces),
+                exclude_interfaces: over.metering.exclude_interfaces.or(self.metering.exclude_interfaces),
+                link_capacities: over.metering.link_capacities.or(self.metering.link_capacities),
+                earning_mode: over.metering.earning_mode.or(self.metering.earning_mode),
+                registry_refresh_secs: over.metering.registry_refresh_secs.or(self.metering.registry_refresh_secs),
+            },
+            reward: RewardSection {
+                threshold: over.reward.threshold.or(self.reward.threshold),
+                upload_threshold: over.reward.upload_threshold.or(self.reward.upload_threshold),
+                download_threshold: over.reward.download_threshold.or(self.reward.download_threshold),
+                upload_rate: over.reward.upload_rate.or(self.reward.upload_rate),
+                download_rate: over.reward.download_rate.or(self.reward.download_rate),
+                cap: over.reward.cap.or(self.reward.cap),
+                rounding: over.reward.rounding.or(self.reward.rounding),
+                interval_secs: over.reward.interval_secs.or(self.reward.interval_secs),
+            },
+            retention: RetentionSection {
+                samples_days: over.retention.samples_days.or(self.retention.samples_days),
+                hourly_days: over.retention.hourly_days.or(self.retention.hourly_days),
+                daily_days: over.retention.daily_days.or(self.retention.daily_days),
+            },
+            logging: LoggingSection {
+                level: over.logging.level.or(self.logging.level),
+                format: over.logging.format.or(self.logging.format),
+            },
+        }
+    }
+}
+
+/// Итоговые проверенные настройки процесса.
+pub struct Settings {
+    pub bind: String,
+    pub port: u16,
+    database_url: Option<String>,
+    pub database_pool: PoolOptions,
+    pub node_id: String,
+    pub reward: RewardPolicy,
+    pub earning_mode: EarningMode,
+    pub interfaces: InterfaceFilter,
+    pub capacities: LinkCapacities,
+    pub registry_refresh: Duration,
+    pub retention: Retention,
+    pub log_level: String,
+    pub log_format: LogFormat,
+}
+
+impl Settings {
+    /// Собирает настройки из файла, окружения и слоя CLI и проверяет их.
+    pub fn load(path: Option<&Path>, cli: ConfigLayer) -> Result<Self, ConfigError> {
+        let file = match path {
+            Some(path) => ConfigLayer::from_file(path)?,
+            None => ConfigLayer::default(),
+        };
+        Self::resolve(file.merge(ConfigLayer::from_env()?).merge(cli))
+    }
+
+    pub fn resolve(layer: ConfigLayer) -> Result<Self, ConfigError> {
+        let defaults = RewardPolicy::default();
+        let reward = &layer.reward;
+        let threshold = reward.threshold.unwrap_or(defaults.upload.threshold);
+        let reward = RewardPolicy {
+            upload: DirectionRate {
+                threshold: reward.upload_threshold.unwrap_or(threshold),
+                rate: reward.upload_rate.unwrap_or(defaults.upload.rate),
+            },
+            download: DirectionRate {
+                threshold: reward.download_threshold.unwrap_or(threshold),
+                rate: reward.download_rate.unwrap_or(defaults.download.rate),
+            },
+            cap: reward.cap.unwrap_or(defaults.cap),
+            rounding: reward.rounding.unwrap_or(defaults.rounding),
+            interval: reward.interval_secs.map_or(defaults.interval, Duration::from_secs),
+        };
+        reward.validate()?;
+
+        let metering = layer.metering;
+        let interfaces = InterfaceFilter::new(
+            &metering.include_interfaces.unwrap_or_default(),
+            &metering.exclude_interfaces.unwrap_or_default(),
+        )?;
+        let capacities = LinkCapacities::new(&metering.link_capacities.unwrap_or_default())?;
+        let registry_refresh = match metering.registry_refresh_secs.unwrap_or(10) {
+            0 => return Err(ConfigError::RegistryRefresh),
+            secs => Duration::from_secs(secs),
+        };
+
+        let defaults = Retention::default();
+        let days = |name: &'static str, days: Option<u64>, default: Option<Duration>| match days {
+            Some(0) => Ok(None),
+            Some(days) if days <= MAX_RETENTION_DAYS => Ok(Some(Duration::from_secs(days * 24 * 3600))),
+            Some(days) => Err(ConfigError::Retention(name, days)),
+            None => Ok(default),
+        };
+        let retention = Retention {
+            samples: days("samples_days", layer.retention.samples_days, defaults.samples)?,
+            hourly: days("hourly_days", layer.retention.hourly_days, defaults.hourly)?,
+            daily: days("daily_days", layer.retention.daily_days, defaults.daily)?,
+        };
+
+        let defaults = PoolOptions::default();
+        let database_pool = PoolOptions {
+            max_size: match layer.database.pool_size {
+                Some(0) => return Err(ConfigError::PoolSize),
+                size => size.unwrap_or(defaults.max_size),
+            },
+            connect_timeout: match layer.database.connect_timeout_secs {
+                Some(0) => return Err(ConfigError::ConnectTimeout),
+                secs => secs.map_or(defaults.connect_timeout, Duration::from_secs),
+            },
+            statement_timeout: match layer.database.statement_timeout_ms {
+                Some(0) => None,
+                Some(ms) => Some(Duration::from_millis(ms)),
+                None => defaults.statement_timeout,
+            },
+        };
+
+        let log_level = layer.logging.level.unwrap_or_else(|| "info".to_string()).to_lowercase();
+        if !LOG_LEVELS.contains(&log_level.as_str()) {
+            return Err(ConfigError::LogLevel(log_level));
+        }
+
+        Ok(Settings {
+            bind: layer.server.bind.unwrap_or_else(|| "0.0.0.0".to_string()),
+            port: layer.server.port.unwrap_or(8080),
+            database_url: layer.database.url,
+            database_pool,
+            node_id: layer
+                .server
+                .node_id
+                .or_else(System::host_name)
+                .unwrap_or_else(|| "local".to_string()),
+            reward,
+            earning_mode: metering.earning_mode.unwrap_or_default(),
+            interfaces,
+            capacities,
+            registry_refresh,
+            retention,
+            log_level,
+            log_format: layer.logging.format.unwrap_or_default(),
+        })
+    }
+
+    /// Адрес базы нужен всем командам, кроме агента, поэтому проверяется отдельно.
+    pub fn database_url(&self) -> Result<&str, ConfigError> {
+        self.database_url.as_deref().ok_or(ConfigError::MissingDatabaseUrl)
+    }
+
+    /// Настройки, которые монитор и обработчики читают во время работы.
+    pub fn node_config(&self) -> NodeConfig {
+        NodeConfig {
+            node_id: self.node_id.clone(),
+            reward: self.reward.clone(),
+            earning_mode: self.earning_mode,
+            interfaces: self.interfaces.clone(),
+            capacities: self.capacities.clone(),
+        }
+    }
+}
+
+/// Правила начисления, которые можно менять на ходу. Монитор берёт их снимок
+/// в начале интервала, так что изменение действует со следующего интервала.
+#[derive(Debug, Clone, Serialize)]
+pub struct ActivePolicy {
+    pub reward: RewardPolicy,
+    pub earning_mode: EarningMode,
+}
+
+pub struct NodeConfig {
+    pub node_id: String,
+    /// Правила начисления поинтов
+    pub reward: RewardPolicy,
+    /// Оплачивается переданный трафик или простаивающая ёмкость линков
+    pub earning_mode: EarningMode,
+    /// Какие интерфейсы учитываются при измерении трафика
+    pub interfaces: InterfaceFilter,
+    /// Пропускная способность интерфейсов для режима простаивающей ёмкости
+    pub capacities: LinkCapacities,
+}
+
+/// Всё, что меняется на ходу (через admin API или SIGHUP) и попадает в журнал аудита.
+#[derive(Debug, Clone, Serialize)]
+pub struct LiveSettings {
+    #[serde(flatten)]
+    pub policy: ActivePolicy,
+    pub interfaces: InterfaceFilter,
+    pub capacities: LinkCapacities,
+}
+
+impl NodeConfig {
+    pub fn policy(&self) -> ActivePolicy {
+        ActivePolicy { reward: self.reward.clone(), earning_mode: self.earning_mode }
+    }
+
+    pub fn live_settings(&self) -> LiveSettings {
+        LiveSettings { policy: self.policy(), interfaces: self.interfaces.clone(), capacities: self.capacities.clone() }
+    }
+
+    pub fn apply(&mut self, settings: LiveSettings) {
+        self.reward = settings.policy.reward;
+        self.earning_mode = settings.policy.earning_mode;
+        self.interfaces = settings.interfaces;
+        self.capacities = settings.capacities;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_override_in_order() {
+        let file: ConfigLayer = toml::from_str(
+            r#"
+            [server]
+            port = 9000
+            bind = "127.0.0.1"
+
+            [database]
+            url = "sqlite://file.db"
+            statement_timeout_ms = 0
+
+            [reward]
+            threshold = 2048
+            upload_threshold = 512
+            interval_secs = 60
+            "#,
+        )
+        .unwrap();
+        let env = ConfigLayer::from_vars(|name| match name {
+            "PORT" => Some("9100".to_string()),
+            "NETWORK_FARMING_THRESHOLD" => Some("4096".to_string()),
+            "NETWORK_FARMING_DB_POOL_SIZE" => Some("4".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        let cli = ConfigLayer {
+            server: ServerSection { port: Some(9200), ..Default::default() },
+            ..Default::default()
+        };
+
+        let settings = Settings::resolve(file.merge(env).merge(cli)).unwrap();
+        assert_eq!(settings.port, 9200);
+        assert_eq!(settings.bind, "127.0.0.1");
+        assert_eq!(settings.database_url().unwrap(), "sqlite://file.db");
+        assert_eq!(settings.database_pool.max_size, 4);
+        assert_eq!(settings.database_pool.statement_timeout, None);
+        // Порог по направлению из файла важнее общего порога из окружения
+        assert_eq!(settings.reward.upload.threshold, 512);
+        assert_eq!(settings.reward.download.threshold, 4096);
+        assert_eq!(settings.reward.interval, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn reports_invalid_values() {
+        let env = ConfigLayer::from_vars(|name| (name == "PORT").then(|| "eighty".to_string()));
+        assert!(matches!(env, Err(ConfigError::Env { var: "PORT", .. })));
+        let env = ConfigLayer::from_vars(|name| (name == "NETWORK_FARMING_LOG_FORMAT").then(|| "xml".to_string()));
+        assert!(matches!(env, Err(ConfigError::Env { var: "NETWORK_FARMING_LOG_FORMAT", .. })));
+
+        assert!(toml::from_str::<ConfigLayer>("[server]\nprot = 80").is_err());
+
+        let layer: ConfigLayer = toml::from_str("[reward]\ncap = 0").unwrap();
+        assert!(matches!(Settings::resolve(layer), Err(ConfigError::Policy(_))));
+
+        let layer: ConfigLayer = toml::from_str("[database]\npool_size = 0").unwrap();
+        assert!(matches!(Settings::resolve(layer), Err(ConfigError::PoolSize)));
+
+        let layer: ConfigLayer = toml::from_str("[retention]\nsamples_days = 300000000000000").unwrap();
+        assert!(matches!(Settings::resolve(layer), Err(ConfigError::Retention("samples_days", _))));
+        let layer: ConfigLayer = toml::from_str(&format!("[retention]\ndaily_days = {}", MAX_RETENTION_DAYS)).unwrap();
+        assert!(Settings::resolve(layer).is_ok());
+
+        let layer: ConfigLayer = toml::from_str("[logging]\nlevel = \"loud\"").unwrap();
+        assert!(matches!(Settings::resolve(layer), Err(ConfigError::LogLevel(_))));
+
+        let settings = Settings::resolve(ConfigLayer::default()).unwrap();
+        assert!(matches!(settings.database_url(), Err(ConfigError::MissingDatabaseUrl)));
+    }
+}
@@ -0,0 +1,121 @@
+// Ошибки веб-API. Каждый обработчик возвращает `ApiError`, который превращается
+// в ответ с нужным статусом и одинаковым телом: `{"error": "<текст>", "code": "<код>"}`.
+use crate::store::StoreError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use std::borrow::Cow;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(Cow<'static, str>),
+    #[error("{0}")]
+    InvalidUsername(&'static str),
+    #[error("{0}")]
+    Unauthorized(&'static str),
+    #[error("{0}")]
+    Forbidden(&'static str),
+    #[error("user not found")]
+    UserNotFound,
+    #[error("{0}")]
+    NotFound(&'static str),
+    #[error("{0}")]
+    Conflict(&'static str),
+    /// Учётные данные не удалось проверить: база недоступна
+    #[error("{0}")]
+    Unavailable(&'static str),
+    #[error("store error: {0}")]
+    Store(StoreError),
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn bad_request(message: impl Into<Cow<'static, str>>) -> Self {
+        ApiError::BadRequest(message.into())
+    }
+
+    /// Машиночитаемый код ошибки для клиентов.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidUsername(_) => "invalid_username",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::UserNotFound => "user_not_found",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Unavailable(_) => "database_unavailable",
+            ApiError::Store(e) if e.is_unavailable() => "database_unavailable",
+            ApiError::Store(_) | ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    // Подробности внутренних ошибок остаются в логе сервера
+    fn message(&self) -> Cow<'_, str> {
+        match self {
+            ApiError::Store(e) if e.is_unavailable() => "database is unavailable, try again later".into(),
+            ApiError::Store(_) | ApiError::Internal(_) => "internal server error".into(),
+            other => other.to_string().into(),
+        }
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::UserNotFound(_) => ApiError::UserNotFound,
+            StoreError::ReportOverlap => ApiError::Conflict("report overlaps an already accepted interval"),
+            e => ApiError::Store(e),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::InvalidUsername(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::UserNotFound | ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Store(e) if e.is_unavailable() => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Store(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    // Подробности 5xx пишет в лог TracingLogger в спане запроса, вместе с его id
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.message(),
+            "code": self.code(),
+        }))
+    }
+}
+
+/// Ошибки разбора JSON, query и пути отдаются в том же формате, что и остальные.
+pub fn extractor_error(error: impl std::fmt::Display, _: &HttpRequest) -> actix_web::Error {
+    ApiError::bad_request(error.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_store_errors_to_statuses() {
+        let missing = ApiError::from(StoreError::UserNotFound("bob".into()));
+        assert_eq!(missing.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(missing.code(), "user_not_found");
+
+        let busy = rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY), None);
+        let busy = ApiError::from(StoreError::Sqlite(busy));
+        assert_eq!(busy.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(busy.code(), "database_unavailable");
+
+        let broken = ApiError::from(StoreError::Sqlite(rusqlite::Error::InvalidQuery));
+        assert_eq!(broken.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(broken.message(), "internal server error");
+    }
+}
@@ -0,0 +1,106 @@
+// Шина событий: каждый обработанный интервал публикуется сюда, а `/stream/{username}`
+// отдаёт события пользователя в браузер через Server-Sent Events.
+use crate::auth::{self, AuthUser};
+use crate::error::ApiError;
+use crate::network::InterfaceUsage;
+use crate::reward::EarningMode;
+use actix_web::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use actix_web::{web, HttpResponse};
+use futures_util::stream;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Сколько событий может накопиться у отстающего подписчика, прежде чем он начнёт их терять.
+const CAPACITY: usize = 256;
+
+/// Пустой комментарий раз в это время, чтобы прокси не закрывали тихое соединение.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Итог одного интервала: измеренный трафик, пороги и начисленные поинты.
+#[derive(Debug, Clone, Serialize)]
+pub struct IntervalEvent {
+    pub username: String,
+    pub node_id: String,
+    pub interval_start: i64,
+    pub interval_end: i64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub interfaces: Vec<InterfaceUsage>,
+    pub earning_mode: EarningMode,
+    /// Оплачиваемые байты в каждом направлении
+    pub billable_sent: u64,
+    pub billable_received: u64,
+    pub upload_threshold: u64,
+    pub download_threshold: u64,
+    /// Начислено за интервал; 0, если трафика не хватило до порога
+    pub points: i64,
+    /// Баланс после начисления; только если поинты начислены
+    pub total_points: Option<i64>,
+}
+
+/// Рассылка событий всем подписчикам процесса. Без подписчиков события просто теряются.
+pub struct EventBus {
+    sender: broadcast::Sender<Arc<IntervalEvent>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus { sender: broadcast::channel(CAPACITY).0 }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: IntervalEvent) {
+        let _ = self.sender.send(Arc::new(event));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<IntervalEvent>> {
+        self.sender.subscribe()
+    }
+}
+
+// Одно SSE-сообщение
+fn sse_frame(event: &str, data: &str) -> web::Bytes {
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+// Поток интервалов пользователя в реальном времени (Server-Sent Events)
+pub async fn stream_events(
+    user: AuthUser,
+    bus: web::Data<Arc<EventBus>>,
+    username: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    auth::validate_username(&username).map_err(ApiError::InvalidUsername)?;
+    if !user.can_access(&username) {
+        return Err(auth::forbidden());
+    }
+
+    let username = username.into_inner();
+    let mut keep_alive = tokio::time::interval(KEEP_ALIVE);
+    keep_alive.reset();
+    let state = (bus.subscribe(), keep_alive, username);
+    let events = stream::unfold(state, |(mut events, mut keep_alive, username)| async move {
+        loop {
+            let frame = tokio::select! {
+                received = events.recv() => match received {
+                    Ok(event) if event.username == username => {
+                        sse_frame("interval", &serde_json::to_string(event.as_ref()).ok()?)
+                    }
+                    Ok(_) => continue,
+                    // Отставший клиент узнаёт, сколько событий пропустил, и получает следующие
+                    Err(RecvError::Lagged(skipped)) => sse_frame("lagged", &skipped.to_string()),
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = keep_alive.tick() => web::Bytes::from_static(b": keep-alive\n\n"),
+            };
+            return Some((Ok::<_, actix_web::Error>(frame), (events, keep_alive, username)));
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .insert_header((CONTENT_TYPE, "text/event-stream"))
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(events))
+}
@@ -0,0 +1,72 @@
+// Проверки для Docker и балансировщиков: `/healthz` — процесс жив,
+// `/readyz` — база отвечает и мониторы завершают интервалы вовремя.
+// Ответы публичные, поэтому имена пользователей в них видит только администратор.
+use crate::api_keys::Scope;
+use crate::auth::AuthUser;
+use crate::monitor::MonitorRegistry;
+use crate::store::PointsStore;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Сколько ждать ответа базы, прежде чем считать её недоступной.
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Монитор считается зависшим, если не завершил интервал за столько своих интервалов.
+const MAX_MISSED_INTERVALS: u32 = 3;
+
+// Процесс запущен и обрабатывает запросы
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok", "version": env!("CARGO_PKG_VERSION") }))
+}
+
+// Готовность принимать трафик: 200, если все компоненты в порядке, иначе 503
+pub async fn readyz(
+    req: HttpRequest,
+    store: web::Data<Arc<dyn PointsStore>>,
+    registry: web::Data<Arc<MonitorRegistry>>,
+) -> HttpResponse {
+    let started = Instant::now();
+    let database = match tokio::time::timeout(DATABASE_TIMEOUT, store.ping()).await {
+        Ok(Ok(())) => json!({ "status": "ok", "latency_ms": started.elapsed().as_millis() as u64 }),
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "Readiness check: database is unreachable");
+            json!({ "status": "failed", "error": "database is unreachable" })
+        }
+        Err(_) => json!({ "status": "failed", "error": "database did not answer in time" }),
+    };
+    let database_ok = database["status"] == "ok";
+
+    let monitors = registry.health(MAX_MISSED_INTERVALS);
+    let monitors_ok = monitors.is_healthy();
+    if !monitors_ok {
+        tracing::warn!(stale = ?monitors.stale, exited = ?monitors.exited, "Readiness check: monitors are not running");
+    }
+
+    let ready = database_ok && monitors_ok;
+    let status = |ok: bool| if ok { "ok" } else { "failed" };
+    let mut body = json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "components": {
+            "database": database,
+            "monitors": {
+                "status": status(monitors_ok),
+                "max_missed_intervals": MAX_MISSED_INTERVALS,
+                "running": monitors.running,
+                "stale": monitors.stale.len(),
+                "exited": monitors.exited.len(),
+            },
+        },
+    });
+    if req.extensions().get::<AuthUser>().is_some_and(|user| user.has_scope(Scope::Admin)) {
+        let details = &mut body["components"]["monitors"];
+        details["stale_users"] = json!(monitors.stale);
+        details["exited_users"] = json!(monitors.exited);
+    }
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
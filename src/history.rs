@@ -0,0 +1,236 @@
+// История трафика: каждый измеренный интервал сохраняется по интерфейсам и
+// сворачивается в часовые и суточные корзины. Старые записи удаляются по срокам
+// хранения, а API отдаёт ряды для графиков.
+use crate::auth::{self, AuthUser};
+use crate::config::NodeConfig;
+use crate::error::ApiError;
+use crate::monitor::{IntervalUsage, RecentUsage};
+use crate::report::MAX_CLOCK_SKEW;
+use crate::store::{self, PointsStore, TrafficQuery, TrafficSample};
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Имя интерфейса для интервалов без разбивки по интерфейсам (старые агенты).
+pub const ALL_INTERFACES: &str = "*";
+
+const HOUR: i64 = 3600;
+const DAY: i64 = 24 * HOUR;
+
+/// Как часто применяются сроки хранения.
+const RETENTION_PERIOD: Duration = Duration::from_secs(3600);
+
+/// Больше точек за один запрос не отдаём: для длинных периодов есть корзины крупнее.
+const MAX_POINTS: i64 = 5000;
+
+/// Сроки хранения истории; `None` — хранить всегда.
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    pub samples: Option<Duration>,
+    pub hourly: Option<Duration>,
+    pub daily: Option<Duration>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            samples: Some(Duration::from_secs(7 * DAY as u64)),
+            hourly: Some(Duration::from_secs(90 * DAY as u64)),
+            daily: None,
+        }
+    }
+}
+
+/// Записи истории для измеренного интервала: по одной на интерфейс.
+pub fn samples(usage: &IntervalUsage) -> Vec<TrafficSample> {
+    let sample = |interface: &str, sent: u64, received: u64| TrafficSample {
+        username: usage.username.clone(),
+        node_id: usage.node_id.clone(),
+        interface: interface.to_string(),
+        interval_start: usage.interval_start,
+        interval_end: usage.interval_end,
+        bytes_sent: sent as i64,
+        bytes_received: received as i64,
+    };
+    if usage.interfaces.is_empty() {
+        return vec![sample(ALL_INTERFACES, usage.bytes_sent, usage.bytes_received)];
+    }
+    usage.interfaces.iter().map(|i| sample(&i.name, i.bytes_sent, i.bytes_received)).collect()
+}
+
+/// Раз в час удаляет историю старше сроков хранения.
+pub async fn run_retention(store: Arc<dyn PointsStore>, retention: Retention) {
+    let mut ticker = tokio::time::interval(RETENTION_PERIOD);
+    loop {
+        ticker.tick().await;
+        let now = store::unix_now();
+        let before = |keep: Option<Duration>| keep.map(|keep| now.saturating_sub(keep.as_secs() as i64));
+        let rollups: Vec<(i64, i64)> = [(HOUR, retention.hourly), (DAY, retention.daily)]
+            .into_iter()
+            .filter_map(|(bucket, keep)| before(keep).map(|before| (bucket, before)))
+            .collect();
+        match store.prune_traffic(before(retention.samples), &rollups).await {
+            Ok(pruned) if pruned.samples > 0 || pruned.rollups > 0 => {
+                tracing::info!(samples = pruned.samples, rollups = pruned.rollups, "Pruned traffic history")
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = %e, "Failed to prune traffic history"),
+        }
+    }
+}
+
+/// Шаг ряда: исходные интервалы, часы или сутки.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    /// Выбрать по длине периода
+    #[default]
+    Auto,
+    Raw,
+    Hour,
+    Day,
+}
+
+impl Step {
+    fn bucket_secs(self) -> Option<i64> {
+        match self {
+            Step::Raw | Step::Auto => None,
+            Step::Hour => Some(HOUR),
+            Step::Day => Some(DAY),
+        }
+    }
+
+    // До 6 часов — исходные интервалы, до двух недель — часы, дальше — сутки
+    fn for_range(range: i64) -> Step {
+        match range {
+            r if r <= 6 * HOUR => Step::Raw,
+            r if r <= 14 * DAY => Step::Hour,
+            _ => Step::Day,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TrafficParams {
+    /// Начало периода, unix-время; по умолчанию сутки назад
+    from: Option<i64>,
+    /// Конец периода (не включая), unix-время; по умолчанию сейчас
+    to: Option<i64>,
+    #[serde(default)]
+    step: Step,
+    node: Option<String>,
+    interface: Option<String>,
+}
+
+// Ряд трафика пользователя для графиков
+pub async fn get_traffic(
+    user: AuthUser,
+    store: web::Data<Arc<dyn PointsStore>>,
+    config: web::Data<Arc<Mutex<NodeConfig>>>,
+    recent: web::Data<Arc<RecentUsage>>,
+    username: web::Path<String>,
+    params: web::Query<TrafficParams>,
+) -> Result<HttpResponse, ApiError> {
+    auth::validate_username(&username).map_err(ApiError::InvalidUsername)?;
+    if !user.can_access(&username) {
+        return Err(auth::forbidden());
+    }
+
+    let params = params.into_inner();
+    // Границы приходят из запроса: вне этого диапазона арифметика ниже переполнилась бы
+    let now = store::unix_now();
+    let valid = 0..=now + MAX_CLOCK_SKEW;
+    let to = params.to.unwrap_or(now);
+    let from = params.from.unwrap_or((to - DAY).max(0));
+    if !valid.contains(&from) || !valid.contains(&to) {
+        return Err(ApiError::bad_request("'from' and 'to' must be unix times between 0 and now"));
+    }
+    if from >= to {
+        return Err(ApiError::bad_request("'from' must be earlier than 'to'"));
+    }
+    let step = match params.step {
+        Step::Auto => Step::for_range(to - from),
+        step => step,
+    };
+    // Исходные записи пишутся раз в интервал монитора по каждому интерфейсу
+    let points = match step.bucket_secs() {
+        Some(bucket) => (to - from) / bucket,
+        None => {
+            let interval = config.lock().unwrap().reward.interval.as_secs().max(1) as i64;
+            let interfaces = match params.interface {
+                Some(_) => 1,
+                None => recent.get(&username).map_or(1, |usage| usage.interfaces.len().max(1)) as i64,
+            };
+            (to - from) / interval * interfaces
+        }
+    };
+    if points > MAX_POINTS {
+        return Err(ApiError::bad_request("period is too long for this step, use a coarser step"));
+    }
+    // Корзины выровнены по своему размеру, поэтому начало периода округляется вниз
+    let from = step.bucket_secs().map_or(from, |bucket| from - from.rem_euclid(bucket));
+
+    let query = TrafficQuery {
+        username: username.into_inner(),
+        from,
+        to,
+        bucket_secs: step.bucket_secs(),
+        node_id: params.node,
+        interface: params.interface,
+    };
+    let series = store.traffic_series(&query).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "username": query.username,
+        "from": from,
+        "to": to,
+        "step": step,
+        "node": query.node_id,
+        "interface": query.interface,
+        "points": series,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{migrations, PoolOptions};
+    use actix_web::ResponseError;
+
+    async fn traffic(query: &str) -> Result<HttpResponse, ApiError> {
+        let store = store::connect("sqlite::memory:", &PoolOptions::default()).await.unwrap();
+        migrations::run(store.as_ref(), false).await.unwrap();
+        store.add_user("bob11", 0).await.unwrap();
+        let config = NodeConfig {
+            node_id: "srv".to_string(),
+            reward: Default::default(),
+            earning_mode: Default::default(),
+            interfaces: Default::default(),
+            capacities: Default::default(),
+        };
+        let user = AuthUser { username: "bob11".to_string(), is_admin: false, key: None };
+        get_traffic(
+            user,
+            web::Data::new(store),
+            web::Data::new(Arc::new(Mutex::new(config))),
+            web::Data::new(Arc::new(RecentUsage::default())),
+            web::Path::from("bob11".to_string()),
+            web::Query::from_query(query).unwrap(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn rejects_out_of_range_periods() {
+        for query in [
+            "from=-9223372036854775808&step=day",
+            "from=-1",
+            "to=9223372036854775807",
+            "from=0&to=9223372036854775807&step=raw",
+        ] {
+            let status = traffic(query).await.map(|r| r.status()).unwrap_or_else(|e| e.status_code());
+            assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST, "{}", query);
+        }
+        assert!(traffic("step=raw").await.unwrap().status().is_success());
+    }
+}
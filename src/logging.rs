@@ -0,0 +1,55 @@
+// Логи процесса на tracing: уровень из настроек (RUST_LOG, если задан, важнее),
+// вывод текстом или JSON в stderr — stdout остаётся за результатами команд.
+// Каждый HTTP-запрос и каждый интервал монитора идут в своём спане.
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::HttpMessage;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::io::IsTerminal;
+use tracing_actix_web::RequestId;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+/// Заголовок ответа с id запроса: по нему строка в логе находится по жалобе клиента.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Строки для чтения человеком
+    #[default]
+    Text,
+    /// Одна JSON-запись на строку, для сборщиков логов
+    Json,
+}
+
+/// Подключает глобальный subscriber. Закрытие спана пишется в лог вместе с его
+/// длительностью — так получаются строки о завершённых запросах и интервалах.
+pub fn init(level: &str, format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().flatten_event(true).with_span_list(false).init(),
+    }
+}
+
+/// Возвращает id запроса, который выдал `TracingLogger`, в заголовке ответа.
+pub async fn request_id_header(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let request_id = req.extensions().get::<RequestId>().copied();
+    let mut response = next.call(req).await?;
+    if let Some(id) = request_id.and_then(|id| HeaderValue::from_str(&id.to_string()).ok()) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, id);
+    }
+    Ok(response)
+}
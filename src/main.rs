@@ -1,11 +1,43 @@
-use actix_web::{web, App, HttpServer, Responder, HttpResponse};
-use actix_files; // Для обслуживания статических файлов
-use clap::Parser;
-use serde::Deserialize;
-use sysinfo::{Networks, System}; // Для работы с сетевым трафиком
-use tokio_postgres::{NoTls, Error}; // Для работы с PostgreSQL
-use serde_json; // Для работы с JSON
-use tokio::time::sleep; // Для асинхронного ожидания
+mod admin;
+mod agent;
+mod api_keys;
+mod auth;
+mod commands;
+mod config;
+mod error;
+mod events;
+mod health;
+mod history;
+mod logging;
+mod metrics;
+mod monitor;
+mod network;
+mod nodes;
+mod report;
+mod reward;
+mod store;
+
+use actix_web::{middleware, web, App, HttpServer, Responder, HttpResponse};
+use actix_web::http::header::{ContentType, CACHE_CONTROL};
+use tracing_actix_web::TracingLogger;
+use clap::{Parser, Subcommand};
+use commands::{ConfigAction, ExportFormat, ExportKind, KeyAction, MigrateAction, PointsAction, UserAction}; // Административные подкоманды
+use config::{
+    ConfigLayer, DatabaseSection, LoggingSection, MeteringSection, NodeConfig, RetentionSection, RewardSection, ServerSection,
+    Settings,
+}; // Конфигурация ноды и её слои
+use monitor::{MonitorRegistry, RecentUsage}; // Мониторы трафика по пользователям
+use reward::{EarningMode, Rounding}; // Политика начисления
+use admin::AdminToken; // Администрирование политики
+use auth::AuthUser; // Вход пользователей и проверка доступа
+use error::ApiError; // Ошибки API с единым форматом ответа
+use logging::LogFormat; // Структурированные логи
+use events::EventBus; // Шина событий для потоков в реальном времени
+use nodes::EnrollmentToken; // Регистрация нод-агентов
+use serde::{Deserialize, Serialize};
+use store::migrations; // Версионированные миграции схемы
+use store::{EarningPeriods, PointsStore, StoreError}; // Хранилище поинтов (PostgreSQL/SQLite)
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::env; // Для работы с переменными окружения
 
@@ -16,209 +48,564 @@ use std::env; // Для работы с переменными окружени
 #[command(version = "1.0")]
 #[command(about = "Farms points from unused network traffic", long_about = None)]
 struct Cli {
-    #[arg(short, long, default_value_t = 1024)]
-    threshold: u64,
-}
+    /// Файл конфигурации TOML (или переменная NETWORK_FARMING_CONFIG); флаги и окружение важнее него
+    #[arg(global = true, long, value_name = "PATH")]
+    config: Option<PathBuf>,
 
-// Шаг 2: Определяем конфигурацию ноды
-#[derive(Deserialize)]
-struct NodeConfig {
-    threshold: u64,
-}
+    /// Адрес, на котором слушает HTTP-сервер (по умолчанию 0.0.0.0)
+    #[arg(global = true, long)]
+    bind: Option<String>,
 
-// Шаг 3: Структура для хранения данных о сетевом трафике
-#[derive(Debug)]
-struct NetworkUsage {
-    sent: u64,
-    received: u64,
-}
+    /// Порт HTTP-сервера (по умолчанию 8080)
+    #[arg(global = true, long)]
+    port: Option<u16>,
 
-impl NetworkUsage {
-    fn new(networks: &Networks) -> Self {
-        let mut total_sent = 0;
-        let mut total_received = 0;
+    /// Адрес базы данных: postgres://…, sqlite://путь или sqlite::memory:
+    #[arg(global = true, long)]
+    database_url: Option<String>,
 
-        for (_interface_name, network) in networks.iter() {
-            total_sent += network.total_transmitted();
-            total_received += network.total_received();
-        }
+    /// Порог в байтах за интервал для обоих направлений (по умолчанию 1024)
+    #[arg(global = true, short, long)]
+    threshold: Option<u64>,
 
-        NetworkUsage {
-            sent: total_sent,
-            received: total_received,
-        }
-    }
+    /// Порог для исходящего трафика (по умолчанию --threshold)
+    #[arg(global = true, long)]
+    upload_threshold: Option<u64>,
 
-    fn get_unused_bandwidth(&self, previous: &NetworkUsage) -> u64 {
-        let current_total = self.sent + self.received;
-        let previous_total = previous.sent + previous.received;
-        if current_total > previous_total {
-            current_total - previous_total
-        } else {
-            0
-        }
-    }
-}
+    /// Порог для входящего трафика (по умолчанию --threshold)
+    #[arg(global = true, long)]
+    download_threshold: Option<u64>,
 
-// Шаг 4: Подключение к базе данных PostgreSQL
-async fn connect_to_db() -> Result<tokio_postgres::Client, Error> {
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    /// Поинтов за байт исходящего трафика сверх порога
+    #[arg(global = true, long)]
+    upload_rate: Option<f64>,
 
-    let (client, connection) = tokio_postgres::connect(&database_url, NoTls).await?;
+    /// Поинтов за байт входящего трафика сверх порога
+    #[arg(global = true, long)]
+    download_rate: Option<f64>,
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("Connection error: {}", e);
-        }
-    });
-
-    client
-        .execute(
-            "CREATE TABLE IF NOT EXISTS users (
-                id SERIAL PRIMARY KEY,
-                username TEXT NOT NULL UNIQUE,
-                points BIGINT NOT NULL
-            )",
-            &[],
-        )
-        .await?;
-
-    Ok(client)
-}
+    /// Не больше стольких поинтов за один интервал
+    #[arg(global = true, long)]
+    points_cap: Option<i64>,
 
-// Шаг 5: Добавление пользователя в базу данных
-async fn add_user(client: &tokio_postgres::Client, username: &str, points: i64) -> Result<(), Error> {
-    client
-        .execute(
-            "INSERT INTO users (username, points) VALUES ($1, $2) ON CONFLICT (username) DO NOTHING",
-            &[&username, &points],
-        )
-        .await?;
+    /// Округление дробных поинтов
+    #[arg(global = true, long, value_enum)]
+    rounding: Option<Rounding>,
 
-    println!("Attempted to add user '{}'.", username);
-    Ok(())
-}
+    /// Длина интервала измерений в секундах
+    #[arg(global = true, long)]
+    interval: Option<u64>,
+
+    /// Идентификатор ноды в журнале начислений (по умолчанию имя хоста)
+    #[arg(global = true, long)]
+    node_id: Option<String>,
+
+    /// Учитывать только интерфейсы по этим маскам (можно повторять); заменяет стандартные исключения
+    #[arg(global = true, long = "include-interface", value_name = "GLOB")]
+    include_interfaces: Vec<String>,
+
+    /// Не учитывать интерфейсы по этим маскам (можно повторять)
+    #[arg(global = true, long = "exclude-interface", value_name = "GLOB")]
+    exclude_interfaces: Vec<String>,
+
+    /// За что начислять поинты: за переданный трафик или за простаивающую ёмкость линков
+    #[arg(global = true, long, value_enum)]
+    earning_mode: Option<EarningMode>,
+
+    /// Пропускная способность интерфейса в Мбит/с (можно повторять); без неё берётся скорость линка из ОС
+    #[arg(global = true, long = "link-capacity", value_name = "INTERFACE=MBITS")]
+    link_capacities: Vec<String>,
 
-// Шаг 6: Получение поинтов пользователя
-async fn get_user_points(client: &tokio_postgres::Client, username: &str) -> Result<i64, Error> {
-    let row = client
-        .query_one("SELECT points FROM users WHERE username = $1", &[&username])
-        .await?;
-    Ok(row.get(0))
+    /// Уровень логирования: error, warn, info, debug, trace (RUST_LOG важнее)
+    #[arg(global = true, long)]
+    log_level: Option<String>,
+
+    /// Формат логов: текст или JSON по записи на строку
+    #[arg(global = true, long, value_enum)]
+    log_format: Option<LogFormat>,
+
+    /// Создать демонстрационного пользователя testuser на этой ноде
+    #[arg(global = true, long)]
+    seed_demo_user: bool,
+
+    /// Как часто (в секундах) сверять список мониторов с аккаунтами в базе
+    #[arg(global = true, long)]
+    registry_refresh: Option<u64>,
+
+    /// Без подкоманды запускается сервер с мониторингом
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-// Шаг 7: Обновление поинтов пользователя
-async fn update_user_points(client: &tokio_postgres::Client, username: &str, points: i64) -> Result<(), Error> {
-    client
-        .execute("UPDATE users SET points = $1 WHERE username = $2", &[&points, &username])
-        .await?;
-    Ok(())
+// Пустой список флагов означает «не задано»: тогда действует список из файла
+fn non_empty(values: Vec<String>) -> Option<Vec<String>> {
+    (!values.is_empty()).then_some(values)
 }
 
-// Шаг 8: Мониторинг сетевого трафика и начисление поинтов
-async fn monitor_network(client: Arc<tokio_postgres::Client>, config: Arc<Mutex<NodeConfig>>, username: String) {
-    let _system = System::new_all();
-    let mut networks = Networks::new_with_refreshed_list();
-    let mut previous_usage = NetworkUsage::new(&networks);
-
-    loop {
-        sleep(tokio::time::Duration::from_secs(30)).await;
-
-        networks.refresh(true);
-        let current_usage = NetworkUsage::new(&networks);
-        let unused_bandwidth = current_usage.get_unused_bandwidth(&previous_usage);
-
-        if unused_bandwidth > config.lock().unwrap().threshold {
-            let threshold = config.lock().unwrap().threshold;
-            let earned_points = ((unused_bandwidth - threshold) as f64 / 1.5).floor() as i64;
-            let earned_points = earned_points.min(10);
-
-            // Получаем текущие поинты пользователя
-            let current_points = get_user_points(&client, &username)
-                .await
-                .unwrap_or(0);
-
-            // Обновляем поинты пользователя
-            let new_points = current_points + earned_points;
-            update_user_points(&client, &username, new_points)
-                .await
-                .expect("Failed to update user points");
-
-            println!(
-                "User: {}, Unused bandwidth: {}, Threshold: {}, Earned points: {}, Total points: {}",
-                username, unused_bandwidth, threshold, earned_points, new_points
-            );
-        } else {
-            println!(
-                "User: {}, Unused bandwidth: {}, Threshold: {}, Not enough traffic to earn points.",
-                username, unused_bandwidth, config.lock().unwrap().threshold
-            );
+impl Cli {
+    /// Флаги командной строки как самый сильный слой настроек.
+    fn layer(&mut self) -> ConfigLayer {
+        ConfigLayer {
+            server: ServerSection { bind: self.bind.take(), port: self.port, node_id: self.node_id.take() },
+            database: DatabaseSection { url: self.database_url.take(), ..Default::default() },
+            metering: MeteringSection {
+                include_interfaces: non_empty(std::mem::take(&mut self.include_interfaces)),
+                exclude_interfaces: non_empty(std::mem::take(&mut self.exclude_interfaces)),
+                link_capacities: non_empty(std::mem::take(&mut self.link_capacities)),
+                earning_mode: self.earning_mode,
+                registry_refresh_secs: self.registry_refresh,
+            },
+            reward: RewardSection {
+                threshold: self.threshold,
+                upload_threshold: self.upload_threshold,
+                download_threshold: self.download_threshold,
+                upload_rate: self.upload_rate,
+                download_rate: self.download_rate,
+                cap: self.points_cap,
+                rounding: self.rounding,
+                interval_secs: self.interval,
+            },
+            retention: RetentionSection::default(),
+            logging: LoggingSection { level: self.log_level.take(), format: self.log_format },
         }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Запустить HTTP-сервер с мониторингом (то же, что запуск без подкоманды)
+    Serve,
+    /// Режим агента: измерять трафик и отправлять подписанные отчёты серверу
+    Agent {
+        /// Адрес сервера, например http://farm.example.com:8080
+        #[arg(long)]
+        server: String,
+        /// Токен регистрации (или переменная ENROLLMENT_TOKEN); нужен только при первом запуске
+        #[arg(long)]
+        enrollment_token: Option<String>,
+        /// API-ключ владельца с правом usage:submit (или переменная NETWORK_FARMING_API_KEY):
+        /// нода регистрируется за ним; нужен только при первом запуске
+        #[arg(long)]
+        api_key: Option<String>,
+        /// Файл с учётными данными ноды
+        #[arg(long, default_value = "agent.json")]
+        credentials: PathBuf,
+    },
+    /// Управление аккаунтами; работающий сервер подхватывает изменения сам
+    User {
+        #[command(subcommand)]
+        action: UserAction,
+    },
+    /// API-ключи для скриптов и нод
+    Key {
+        #[command(subcommand)]
+        action: KeyAction,
+    },
+    /// Ручные корректировки баланса
+    Points {
+        #[command(subcommand)]
+        action: PointsAction,
+    },
+    /// Выгрузить аккаунты или журнал начислений
+    Export {
+        #[arg(value_enum)]
+        kind: ExportKind,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Только этот пользователь
+        #[arg(long)]
+        user: Option<String>,
+        /// Файл для выгрузки (по умолчанию stdout)
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Управление миграциями схемы базы данных
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Проверка конфигурации
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
 
-        previous_usage = current_usage;
+// Шаг 2: Подключение к хранилищу (PostgreSQL или SQLite)
+async fn connect_to_db(settings: &Settings) -> Result<Arc<dyn PointsStore>, Box<dyn std::error::Error>> {
+    let database_url = settings.database_url()?;
+    Ok(store::connect(database_url, &settings.database_pool).await?)
+}
+
+// Шаг 3: Добавление пользователя в базу данных
+async fn add_user(store: &dyn PointsStore, username: &str, points: i64) -> Result<(), StoreError> {
+    if store.add_user(username, points).await? {
+        tracing::info!(user = username, "Added user");
+    } else {
+        tracing::info!(user = username, "User already exists");
     }
+    Ok(())
 }
 
-// Шаг 9: Главная страница (HTML)
+// Шаг 4: Панель пользователя (HTML, встроена в бинарник)
 async fn index() -> impl Responder {
-    HttpResponse::Ok().body(include_str!("index.html"))
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .body(include_str!("index.html"))
+}
+
+// Неизвестный маршрут — тот же JSON-формат ошибки, что и у остальных
+async fn not_found() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::NotFound("no such endpoint"))
 }
 
-// Шаг 10: Получение статистики через API
+// Период, за который /stats показывает earned_points
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+enum Period {
+    #[serde(rename = "last_interval")]
+    LastInterval,
+    #[serde(rename = "today")]
+    Today,
+    #[serde(rename = "7d")]
+    Days7,
+    #[serde(rename = "30d")]
+    Days30,
+    #[default]
+    #[serde(rename = "all")]
+    All,
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    #[serde(default)]
+    period: Period,
+}
+
+// Шаг 5: Получение статистики через API
 async fn get_stats(
-    client: web::Data<Arc<tokio_postgres::Client>>,
+    user: AuthUser, // Только вошедший пользователь
+    store: web::Data<Arc<dyn PointsStore>>,
     config: web::Data<Arc<Mutex<NodeConfig>>>,
+    recent: web::Data<Arc<RecentUsage>>,
     username: web::Path<String>, // Получаем username из URL
-) -> impl Responder {
-    let threshold = config.lock().unwrap().threshold;
+    query: web::Query<StatsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    // Опечатка в имени — ошибка запроса, а не пустая статистика
+    auth::validate_username(&username).map_err(ApiError::InvalidUsername)?;
+    // Свою статистику видит каждый, чужую — только администратор
+    if !user.can_access(&username) {
+        return Err(auth::forbidden());
+    }
 
-    // Получаем поинты пользователя
-    let total_points = get_user_points(&client, &username)
-        .await
-        .unwrap_or(0);
+    let (reward, earning_mode) = {
+        let config = config.lock().unwrap();
+        (config.reward.clone(), config.earning_mode)
+    };
+
+    // Заработок считаем по журналу начислений, а не по балансу
+    let now = store::unix_now();
+    let earnings = store.earnings(&username, &EarningPeriods::ending_at(now)).await?;
+    let node = store.node_status(&username).await?;
+    let total_points = store.get_user_points(&username).await?;
+
+    // Последний измеренный интервал мог не принести поинтов — тогда в журнале его нет
+    let last_interval = recent.get(&username);
+    let last_credit_end = earnings.last_credit.as_ref().and_then(|credit| credit.interval_end);
+    let last_interval_points = match (&last_interval, &earnings.last_credit) {
+        (Some(usage), _) if Some(usage.interval_end) != last_credit_end => 0,
+        (_, Some(credit)) => credit.points,
+        _ => 0,
+    };
+
+    let last_seen_at = node.last_seen_at.max(last_interval.as_ref().map(|usage| usage.interval_end));
+    let online_window = monitor::online_window(reward.interval);
+    let status = match last_seen_at {
+        _ if !node.enabled => "disabled",
+        None => "never_seen",
+        Some(seen) if now - seen <= online_window => "online",
+        Some(_) => "offline",
+    };
+
+    let earned_points = match query.period {
+        Period::LastInterval => last_interval_points,
+        Period::Today => earnings.today,
+        Period::Days7 => earnings.last_7_days,
+        Period::Days30 => earnings.last_30_days,
+        Period::All => earnings.all_time,
+    };
 
     // Разыменовываем username с помощью *
-    HttpResponse::Ok().json(serde_json::json!({
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "username": *username,
+        "reward": reward, // Действующая политика начисления
+        "earning_mode": earning_mode, // За трафик или за простаивающую ёмкость
+        "period": query.period,
+        "earned_points": earned_points, // Заработок за выбранный период
+        "earnings": {
+            "last_interval": last_interval_points,
+            "today": earnings.today,
+            "7d": earnings.last_7_days,
+            "30d": earnings.last_30_days,
+            "all": earnings.all_time,
+        },
+        "total_points": total_points, // Баланс с учётом корректировок
+        "last_credit": earnings.last_credit,
+        "node": {
+            "node_id": node.node_id,
+            "status": status,
+            "last_seen_at": last_seen_at,
+        },
+        "last_interval": last_interval // Трафик последнего интервала по интерфейсам
+    })))
+}
+
+// Параметры постраничного вывода журнала
+#[derive(Deserialize)]
+struct HistoryQuery {
+    limit: Option<i64>,
+    before: Option<i64>,
+}
+
+// Шаг 6: История начислений пользователя из журнала
+async fn get_history(
+    user: AuthUser,
+    store: web::Data<Arc<dyn PointsStore>>,
+    username: web::Path<String>,
+    query: web::Query<HistoryQuery>,
+) -> Result<HttpResponse, ApiError> {
+    auth::validate_username(&username).map_err(ApiError::InvalidUsername)?;
+    if !user.can_access(&username) {
+        return Err(auth::forbidden());
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+
+    let reconciliation = store.reconcile(&username).await?;
+    let transactions = store.list_transactions(&username, limit, query.before).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "username": *username,
+        "balance": reconciliation.balance,
+        "ledger_balance": reconciliation.ledger_balance,
+        "consistent": reconciliation.is_consistent(),
+        "transactions": transactions
+    })))
+}
+
+// Параметры графика заработка по суткам
+#[derive(Deserialize)]
+struct EarningsQuery {
+    days: Option<i64>,
+    /// Сдвиг часового пояса клиента от UTC в секундах, чтобы сутки совпадали с его календарём
+    utc_offset: Option<i64>,
+}
+
+// Заработок за трафик по суткам для графика на панели; дни без начислений идут с нулём
+async fn get_daily_earnings(
+    user: AuthUser,
+    store: web::Data<Arc<dyn PointsStore>>,
+    username: web::Path<String>,
+    query: web::Query<EarningsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    const DAY: i64 = 24 * 3600;
+    auth::validate_username(&username).map_err(ApiError::InvalidUsername)?;
+    if !user.can_access(&username) {
+        return Err(auth::forbidden());
+    }
+    let days = query.days.unwrap_or(30).clamp(1, 366);
+    let utc_offset = query.utc_offset.unwrap_or(0).clamp(-14 * 3600, 14 * 3600);
+
+    let now = store::unix_now();
+    let today = (now + utc_offset).div_euclid(DAY) * DAY - utc_offset;
+    let since = today - (days - 1) * DAY;
+    let earned = store.daily_earnings(&username, since, utc_offset).await?;
+    let days: Vec<_> = (0..days)
+        .map(|day| {
+            let day_start = since + day * DAY;
+            let points = earned.iter().find(|e| e.day_start == day_start).map_or(0, |e| e.points);
+            serde_json::json!({ "day_start": day_start, "points": points })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
         "username": *username,
-        "threshold": threshold,
-        "earned_points": total_points / 2, // Примерное значение
-        "total_points": total_points
-    }))
+        "utc_offset": utc_offset,
+        "days": days
+    })))
 }
 
-// Шаг 11: Основной код приложения
+// Шаг 7: Основной код приложения
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let port = env::var("PORT")
-        .unwrap_or_else(|_| "8080".to_string())
-        .parse::<u16>()
-        .expect("PORT must be a number");
+    let mut cli = Cli::parse();
+
+    // Файл из --config, иначе из NETWORK_FARMING_CONFIG; настройки проверяются до любой работы
+    let config_path = cli.config.take().or_else(|| env::var_os(config::CONFIG_PATH_ENV).map(PathBuf::from));
+    let cli_layer = cli.layer();
+    let settings = match Settings::load(config_path.as_deref(), cli_layer.clone()) {
+        Ok(settings) => settings,
+        Err(e) => {
+            // Логи ещё не настроены: без настроек неизвестны ни уровень, ни формат
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    logging::init(&settings.log_level, settings.log_format);
+
+    // Агенту база данных не нужна
+    if let Some(Command::Agent { server, enrollment_token, api_key, credentials }) = cli.command {
+        let options = agent::AgentOptions {
+            server,
+            node_id: settings.node_id,
+            enrollment_token: enrollment_token.or_else(|| env::var("ENROLLMENT_TOKEN").ok()),
+            api_key: api_key.or_else(|| env::var("NETWORK_FARMING_API_KEY").ok()),
+            credentials_path: credentials,
+            interfaces: settings.interfaces,
+            capacities: settings.capacities,
+            interval: settings.reward.interval,
+        };
+        if let Err(e) = agent::run(options).await {
+            tracing::error!(error = %e, "Agent stopped");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Config { action }) = cli.command {
+        commands::run_config(&settings, action);
+        return Ok(());
+    }
+
+    let store = match connect_to_db(&settings).await {
+        Ok(store) => store,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to connect to the database");
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(Command::Migrate { action }) = cli.command {
+        if let Err(e) = commands::run_migrate(store.as_ref(), action).await {
+            tracing::error!(error = %e, "Migration failed");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    let client = Arc::new(connect_to_db().await.expect("Failed to connect to the database"));
+    // Остальные команды всегда работают на актуальной схеме
+    match migrations::run(store.as_ref(), false).await {
+        Ok(applied) => {
+            for migration in applied {
+                tracing::info!(version = migration.version, name = migration.name, "Applied migration");
+            }
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to apply database migrations");
+            std::process::exit(1);
+        }
+    }
 
-    let cli = Cli::parse();
-    let config = Arc::new(Mutex::new(NodeConfig { threshold: cli.threshold }));
+    // Административные команды выполняются и завершаются; без подкоманды и с serve запускается сервер
+    let outcome = match cli.command {
+        Some(Command::User { action }) => Some(commands::run_user(store.as_ref(), action).await),
+        Some(Command::Key { action }) => Some(commands::run_key(store.as_ref(), action).await),
+        Some(Command::Points { action }) => Some(commands::run_points(store.as_ref(), action).await),
+        Some(Command::Export { kind, format, user, output }) => {
+            Some(commands::run_export(store.as_ref(), kind, format, user, output).await)
+        }
+        _ => None,
+    };
+    if let Some(result) = outcome {
+        if let Err(e) = result {
+            eprintln!("Command failed: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    // Добавляем тестового пользователя (если нужно)
-    add_user(&client, "testuser", 0)
-        .await
-        .expect("Failed to add user");
+    // Серверу нужны замеры базы; административные команды выше обходятся без них
+    let store = metrics::InstrumentedStore::wrap(store);
+    let config = Arc::new(Mutex::new(settings.node_config()));
+    let recent = Arc::new(RecentUsage::default());
 
-    // Запускаем мониторинг трафика для конкретного пользователя
-    let db_client_clone = Arc::clone(&client);
-    tokio::spawn(monitor_network(db_client_clone, config.clone(), "testuser".to_string()));
+    // Добавляем тестового пользователя, только если об этом попросили
+    if cli.seed_demo_user {
+        if let Err(e) = add_user(store.as_ref(), "testuser", 0).await {
+            tracing::error!(error = %e, "Failed to add demo user");
+            std::process::exit(1);
+        }
+    }
 
+    // Запускаем мониторы для всех активных аккаунтов этой ноды
+    let events = Arc::new(EventBus::default());
+    let registry = Arc::new(MonitorRegistry::new(
+        Arc::clone(&store),
+        config.clone(),
+        recent.clone(),
+        events.clone(),
+    ));
+    tokio::spawn(Arc::clone(&registry).run(settings.registry_refresh));
+
+    // Старая история трафика удаляется по срокам хранения
+    tokio::spawn(history::run_retention(Arc::clone(&store), settings.retention));
+
+    // По SIGHUP перечитываем файл конфигурации
+    tokio::spawn(admin::reload_on_sighup(Arc::clone(&store), config.clone(), config_path, cli_layer));
+
+    // Без ENROLLMENT_TOKEN новые ноды зарегистрировать нельзя
+    let enrollment = web::Data::new(EnrollmentToken(env::var("ENROLLMENT_TOKEN").ok()));
+    // ADMIN_TOKEN открывает admin API и без аккаунта администратора
+    let admin_token = web::Data::new(AdminToken(env::var("ADMIN_TOKEN").ok()));
+
+    tracing::info!(
+        bind = %settings.bind,
+        port = settings.port,
+        node = %settings.node_id,
+        log_level = %settings.log_level,
+        "Serving"
+    );
+    let bind = (settings.bind.clone(), settings.port);
     HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(Arc::clone(&client)))
+            .app_data(web::Data::new(Arc::clone(&store)))
             .app_data(web::Data::new(config.clone()))
+            .app_data(enrollment.clone())
+            .app_data(admin_token.clone())
+            .app_data(web::Data::new(recent.clone()))
+            .app_data(web::Data::new(events.clone()))
+            .app_data(web::Data::new(registry.clone()))
+            // Ошибки разбора запроса — в том же JSON-формате, что и остальные
+            .app_data(web::JsonConfig::default().error_handler(error::extractor_error))
+            .app_data(web::QueryConfig::default().error_handler(error::extractor_error))
+            .app_data(web::PathConfig::default().error_handler(error::extractor_error))
+            .wrap(middleware::from_fn(auth::identify)) // Сессии и API-ключи
+            .wrap(middleware::from_fn(metrics::track_requests)) // Счётчики и задержки запросов
+            .wrap(middleware::from_fn(logging::request_id_header)) // X-Request-Id в ответе
+            .wrap(TracingLogger::default()) // Спан и request id на каждый запрос
             .route("/", web::get().to(index))
+            .route("/auth/signup", web::post().to(auth::signup)) // Регистрация
+            .route("/auth/login", web::post().to(auth::login)) // Вход
+            .route("/auth/logout", web::post().to(auth::logout)) // Выход
+            .route("/auth/me", web::get().to(auth::me)) // Текущий пользователь
+            .route("/api-keys", web::post().to(api_keys::create_key)) // Выпуск API-ключа
+            .route("/api-keys", web::get().to(api_keys::list_keys)) // Список ключей
+            .route("/api-keys/{id}", web::delete().to(api_keys::revoke_key)) // Отзыв ключа
             .route("/stats/{username}", web::get().to(get_stats)) // Маршрут для получения статистики
-            .service(
-                actix_files::Files::new("/static", "./static").show_files_listing(),
-            )
+            .route("/stats/{username}/history", web::get().to(get_history)) // Журнал начислений
+            .route("/stats/{username}/earnings", web::get().to(get_daily_earnings)) // Заработок по суткам
+            .route("/users/{username}/traffic", web::get().to(history::get_traffic)) // История трафика для графиков
+            .route("/healthz", web::get().to(health::healthz)) // Процесс жив
+            .route("/readyz", web::get().to(health::readyz)) // База и мониторы в порядке
+            .route("/metrics", web::get().to(metrics::get_metrics)) // Метрики Prometheus
+            .route("/stream/{username}", web::get().to(events::stream_events)) // Интервалы в реальном времени (SSE)
+            .route("/nodes/enroll", web::post().to(nodes::enroll_node)) // Регистрация агента
+            .route("/nodes/{node_id}/reports", web::post().to(nodes::submit_report)) // Отчёты агентов
+            .route("/admin/policy", web::get().to(admin::get_policy)) // Действующая политика
+            .route("/admin/policy", web::patch().to(admin::update_policy)) // Изменение политики на ходу
+            .route("/admin/audit", web::get().to(admin::get_audit_log)) // Журнал изменений политики
+            .default_service(web::to(not_found))
     })
-    .bind(("0.0.0.0", port))?
+    .bind(bind)?
     .run()
     .await
 }
\ No newline at end of file
@@ -0,0 +1,368 @@
+// Метрики Prometheus: трафик и начисления, работа мониторов, задержки базы и
+// HTTP-запросов. Отдаются в текстовом формате на `/metrics`.
+use crate::config::NodeConfig;
+use crate::events::IntervalEvent;
+use crate::history::ALL_INTERFACES;
+use crate::monitor;
+use crate::store::migrations::{AppliedMigration, Migration};
+use crate::store::{
+    self, Account, ApiKey, ApiKeyOwner, Binding, ConfigChange, Credentials, DailyEarning, EarningPeriods, Earnings,
+    Enrollment, NewApiKey, NewTransaction, Node, NodeStatus, PointsStore, PrunedTraffic, Reconciliation, SessionUser,
+    StoreError, TrafficPoint, TrafficQuery, TrafficSample, Transaction,
+};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use async_trait::async_trait;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex};
+
+const NAMESPACE: &str = "network_farming";
+
+/// Все метрики процесса. Регистрируются один раз при первом обращении.
+pub struct Metrics {
+    registry: Registry,
+    /// Измеренный трафик по ноде, интерфейсу и направлению
+    pub traffic_bytes: IntCounterVec,
+    pub points_credited: IntCounterVec,
+    pub monitor_iterations: IntCounter,
+    pub monitor_failures: IntCounter,
+    pub db_query_seconds: HistogramVec,
+    pub db_errors: IntCounterVec,
+    pub http_requests: IntCounterVec,
+    pub http_request_seconds: HistogramVec,
+    /// Ноды, отчитавшиеся за последние два интервала; обновляется при каждом опросе `/metrics`
+    pub active_nodes: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let opts = |name: &str, help: &str| Opts::new(name, help).namespace(NAMESPACE);
+        let histogram = |name: &str, help: &str| HistogramOpts::new(name, help).namespace(NAMESPACE);
+        let metrics = Metrics {
+            registry: Registry::new(),
+            traffic_bytes: IntCounterVec::new(
+                opts("traffic_bytes_total", "Bytes measured per node, interface and direction"),
+                &["node", "interface", "direction"],
+            )
+            .unwrap(),
+            points_credited: IntCounterVec::new(opts("points_credited_total", "Points credited for traffic"), &["node"])
+                .unwrap(),
+            monitor_iterations: IntCounter::with_opts(opts(
+                "monitor_iterations_total",
+                "Intervals measured by the local monitor loops",
+            ))
+            .unwrap(),
+            monitor_failures: IntCounter::with_opts(opts(
+                "monitor_failures_total",
+                "Monitor loop intervals that failed to be credited",
+            ))
+            .unwrap(),
+            db_query_seconds: HistogramVec::new(
+                histogram("db_query_duration_seconds", "Database operation latency"),
+                &["operation"],
+            )
+            .unwrap(),
+            db_errors: IntCounterVec::new(opts("db_errors_total", "Failed database operations"), &["operation"])
+                .unwrap(),
+            http_requests: IntCounterVec::new(
+                opts("http_requests_total", "HTTP requests per route and status"),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            http_request_seconds: HistogramVec::new(
+                histogram("http_request_duration_seconds", "HTTP request latency per route"),
+                &["method", "route"],
+            )
+            .unwrap(),
+            active_nodes: IntGauge::with_opts(opts("active_nodes", "Nodes that reported within the last two intervals"))
+                .unwrap(),
+        };
+        let collectors: [Box<dyn prometheus::core::Collector>; 9] = [
+            Box::new(metrics.traffic_bytes.clone()),
+            Box::new(metrics.points_credited.clone()),
+            Box::new(metrics.monitor_iterations.clone()),
+            Box::new(metrics.monitor_failures.clone()),
+            Box::new(metrics.db_query_seconds.clone()),
+            Box::new(metrics.db_errors.clone()),
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.http_request_seconds.clone()),
+            Box::new(metrics.active_nodes.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Учитывает обработанный интервал: трафик по интерфейсам и начисленные поинты.
+/// Имена интерфейсов из отчётов агентов в метки не попадают: их выбирает нода,
+/// и каждое новое имя стало бы отдельной серией. Трафик удалённых нод идёт под `*`.
+pub fn observe_interval(event: &IntervalEvent, source: monitor::Source) {
+    let traffic = &metrics().traffic_bytes;
+    let add = |interface: &str, sent: u64, received: u64| {
+        traffic.with_label_values(&[&event.node_id, interface, "sent"]).inc_by(sent);
+        traffic.with_label_values(&[&event.node_id, interface, "received"]).inc_by(received);
+    };
+    let interfaces = match source {
+        monitor::Source::Local => event.interfaces.as_slice(),
+        monitor::Source::Report => &[],
+    };
+    if interfaces.is_empty() {
+        add(ALL_INTERFACES, event.bytes_sent, event.bytes_received);
+    }
+    for interface in interfaces {
+        add(&interface.name, interface.bytes_sent, interface.bytes_received);
+    }
+    if event.points > 0 {
+        metrics().points_credited.with_label_values(&[&event.node_id]).inc_by(event.points as u64);
+    }
+}
+
+/// Считает запросы и их длительность по шаблону маршрута, а не по пути:
+/// иначе каждое имя пользователя стало бы отдельной серией.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+    let timer = metrics().http_request_seconds.with_label_values(&[&method, &route]).start_timer();
+    let response = next.call(req).await;
+    timer.observe_duration();
+    let status = match &response {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    metrics().http_requests.with_label_values(&[&method, &route, status.as_str()]).inc();
+    response
+}
+
+// Метрики в текстовом формате Prometheus
+pub async fn get_metrics(
+    store: web::Data<Arc<dyn PointsStore>>,
+    config: web::Data<Arc<Mutex<NodeConfig>>>,
+) -> HttpResponse {
+    let interval = config.lock().unwrap().policy().reward.interval;
+    let since = store::unix_now() - monitor::online_window(interval);
+    match store.count_active_nodes(since).await {
+        Ok(count) => metrics().active_nodes.set(count),
+        Err(e) => tracing::warn!(error = %e, "Failed to count active nodes"),
+    }
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    if let Err(e) = encoder.encode(&metrics().registry.gather(), &mut body) {
+        tracing::error!(error = %e, "Failed to encode metrics");
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().content_type(encoder.format_type()).body(body)
+}
+
+/// Хранилище, которое замеряет время и ошибки каждой операции.
+pub struct InstrumentedStore {
+    inner: Arc<dyn PointsStore>,
+}
+
+impl InstrumentedStore {
+    pub fn wrap(inner: Arc<dyn PointsStore>) -> Arc<dyn PointsStore> {
+        Arc::new(InstrumentedStore { inner })
+    }
+}
+
+async fn timed<T>(
+    operation: &'static str,
+    query: impl Future<Output = Result<T, StoreError>>,
+) -> Result<T, StoreError> {
+    let timer = metrics().db_query_seconds.with_label_values(&[operation]).start_timer();
+    let result = query.await;
+    timer.observe_duration();
+    if result.is_err() {
+        metrics().db_errors.with_label_values(&[operation]).inc();
+    }
+    result
+}
+
+#[async_trait]
+impl PointsStore for InstrumentedStore {
+    async fn ping(&self) -> Result<(), StoreError> {
+        timed("ping", self.inner.ping()).await
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<AppliedMigration>, StoreError> {
+        timed("applied_migrations", self.inner.applied_migrations()).await
+    }
+
+    async fn apply_migration(&self, migration: &Migration) -> Result<bool, StoreError> {
+        timed("apply_migration", self.inner.apply_migration(migration)).await
+    }
+
+    async fn add_user(&self, username: &str, points: i64) -> Result<bool, StoreError> {
+        timed("add_user", self.inner.add_user(username, points)).await
+    }
+
+    async fn list_users(&self) -> Result<Vec<Account>, StoreError> {
+        timed("list_users", self.inner.list_users()).await
+    }
+
+    async fn monitored_users(&self, node_id: &str) -> Result<Vec<String>, StoreError> {
+        timed("monitored_users", self.inner.monitored_users(node_id)).await
+    }
+
+    async fn set_user_enabled(&self, username: &str, enabled: bool) -> Result<(), StoreError> {
+        timed("set_user_enabled", self.inner.set_user_enabled(username, enabled)).await
+    }
+
+    async fn set_user_node(&self, username: &str, node_id: Option<&str>) -> Result<Binding, StoreError> {
+        timed("set_user_node", self.inner.set_user_node(username, node_id)).await
+    }
+
+    async fn delete_user(&self, username: &str) -> Result<(), StoreError> {
+        timed("delete_user", self.inner.delete_user(username)).await
+    }
+
+    async fn create_node(&self, node_id: &str, username: &str, secret: &str) -> Result<Enrollment, StoreError> {
+        timed("create_node", self.inner.create_node(node_id, username, secret)).await
+    }
+
+    async fn get_node(&self, node_id: &str) -> Result<Option<Node>, StoreError> {
+        timed("get_node", self.inner.get_node(node_id)).await
+    }
+
+    async fn accept_node_report(
+        &self,
+        node_id: &str,
+        interval_start: i64,
+        interval_end: i64,
+        credit: Option<&NewTransaction>,
+    ) -> Result<Option<i64>, StoreError> {
+        timed("accept_node_report", self.inner.accept_node_report(node_id, interval_start, interval_end, credit)).await
+    }
+
+    async fn get_user_points(&self, username: &str) -> Result<i64, StoreError> {
+        timed("get_user_points", self.inner.get_user_points(username)).await
+    }
+
+    async fn credit_points(&self, entry: &NewTransaction) -> Result<i64, StoreError> {
+        timed("credit_points", self.inner.credit_points(entry)).await
+    }
+
+    async fn list_transactions(
+        &self,
+        username: &str,
+        limit: i64,
+        before: Option<i64>,
+    ) -> Result<Vec<Transaction>, StoreError> {
+        timed("list_transactions", self.inner.list_transactions(username, limit, before)).await
+    }
+
+    async fn reconcile(&self, username: &str) -> Result<Reconciliation, StoreError> {
+        timed("reconcile", self.inner.reconcile(username)).await
+    }
+
+    async fn earnings(&self, username: &str, periods: &EarningPeriods) -> Result<Earnings, StoreError> {
+        timed("earnings", self.inner.earnings(username, periods)).await
+    }
+
+    async fn daily_earnings(
+        &self,
+        username: &str,
+        since: i64,
+        utc_offset: i64,
+    ) -> Result<Vec<DailyEarning>, StoreError> {
+        timed("daily_earnings", self.inner.daily_earnings(username, since, utc_offset)).await
+    }
+
+    async fn node_status(&self, username: &str) -> Result<NodeStatus, StoreError> {
+        timed("node_status", self.inner.node_status(username)).await
+    }
+
+    async fn count_active_nodes(&self, since: i64) -> Result<i64, StoreError> {
+        timed("count_active_nodes", self.inner.count_active_nodes(since)).await
+    }
+
+    async fn record_traffic(&self, samples: &[TrafficSample]) -> Result<(), StoreError> {
+        timed("record_traffic", self.inner.record_traffic(samples)).await
+    }
+
+    async fn traffic_series(&self, query: &TrafficQuery) -> Result<Vec<TrafficPoint>, StoreError> {
+        timed("traffic_series", self.inner.traffic_series(query)).await
+    }
+
+    async fn prune_traffic(
+        &self,
+        samples_before: Option<i64>,
+        rollups_before: &[(i64, i64)],
+    ) -> Result<PrunedTraffic, StoreError> {
+        timed("prune_traffic", self.inner.prune_traffic(samples_before, rollups_before)).await
+    }
+
+    async fn create_account(&self, username: &str, password_hash: &str) -> Result<bool, StoreError> {
+        timed("create_account", self.inner.create_account(username, password_hash)).await
+    }
+
+    async fn set_password(&self, username: &str, password_hash: &str) -> Result<(), StoreError> {
+        timed("set_password", self.inner.set_password(username, password_hash)).await
+    }
+
+    async fn set_user_admin(&self, username: &str, is_admin: bool) -> Result<(), StoreError> {
+        timed("set_user_admin", self.inner.set_user_admin(username, is_admin)).await
+    }
+
+    async fn get_credentials(&self, username: &str) -> Result<Option<Credentials>, StoreError> {
+        timed("get_credentials", self.inner.get_credentials(username)).await
+    }
+
+    async fn create_session(&self, username: &str, token_hash: &str, expires_at: i64) -> Result<(), StoreError> {
+        timed("create_session", self.inner.create_session(username, token_hash, expires_at)).await
+    }
+
+    async fn get_session(&self, token_hash: &str) -> Result<Option<SessionUser>, StoreError> {
+        timed("get_session", self.inner.get_session(token_hash)).await
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), StoreError> {
+        timed("delete_session", self.inner.delete_session(token_hash)).await
+    }
+
+    async fn create_api_key(&self, key: &NewApiKey) -> Result<i64, StoreError> {
+        timed("create_api_key", self.inner.create_api_key(key)).await
+    }
+
+    async fn list_api_keys(&self, username: Option<&str>) -> Result<Vec<ApiKey>, StoreError> {
+        timed("list_api_keys", self.inner.list_api_keys(username)).await
+    }
+
+    async fn revoke_api_key(&self, id: i64, username: Option<&str>) -> Result<bool, StoreError> {
+        timed("revoke_api_key", self.inner.revoke_api_key(id, username)).await
+    }
+
+    async fn authenticate_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyOwner>, StoreError> {
+        timed("authenticate_api_key", self.inner.authenticate_api_key(key_hash)).await
+    }
+
+    async fn record_config_change(
+        &self,
+        source: &str,
+        node_id: &str,
+        previous: &serde_json::Value,
+        current: &serde_json::Value,
+    ) -> Result<(), StoreError> {
+        timed("record_config_change", self.inner.record_config_change(source, node_id, previous, current)).await
+    }
+
+    async fn list_config_changes(&self, limit: i64) -> Result<Vec<ConfigChange>, StoreError> {
+        timed("list_config_changes", self.inner.list_config_changes(limit)).await
+    }
+}
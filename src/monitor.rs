@@ -0,0 +1,413 @@
+// Мониторинг трафика: цикл начисления для одного пользователя и реестр,
+// который держит монитор владельца этой ноды
+use crate::config::{ActivePolicy, NodeConfig};
+use crate::events::{EventBus, IntervalEvent};
+use crate::history;
+use crate::metrics::{self, metrics};
+use crate::network::{self, Counters, InterfaceUsage, Meter, NetworkUsage};
+use crate::reward::EarningMode;
+use crate::store::{self, NewTransaction, PointsStore, StoreError, TrafficDetails};
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use sysinfo::Networks;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+/// Трафик пользователя за один интервал — от локального монитора или из отчёта агента.
+#[derive(Debug, Clone, Serialize)]
+pub struct IntervalUsage {
+    pub username: String,
+    pub node_id: String,
+    pub interval_start: i64,
+    pub interval_end: i64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub interfaces: Vec<InterfaceUsage>,
+}
+
+impl IntervalUsage {
+    /// Сколько байт в каждом направлении оплачивается в данном режиме:
+    /// переданный трафик или незанятая ёмкость линков.
+    fn billable(&self, mode: EarningMode) -> Counters {
+        match mode {
+            EarningMode::Contributed => Counters { sent: self.bytes_sent, received: self.bytes_received },
+            EarningMode::IdleCapacity => {
+                let secs = (self.interval_end - self.interval_start).max(0) as u64;
+                network::idle_capacity(&self.interfaces, secs)
+            }
+        }
+    }
+}
+
+/// Последний измеренный интервал каждого пользователя — для API статистики.
+#[derive(Default)]
+pub struct RecentUsage {
+    latest: Mutex<HashMap<String, IntervalUsage>>,
+}
+
+impl RecentUsage {
+    pub fn record(&self, usage: &IntervalUsage) {
+        self.latest.lock().unwrap().insert(usage.username.clone(), usage.clone());
+    }
+
+    pub fn get(&self, username: &str) -> Option<IntervalUsage> {
+        self.latest.lock().unwrap().get(username).cloned()
+    }
+}
+
+// Запас на задержки отчётов при проверке, в сети ли нода
+const ONLINE_GRACE_SECS: i64 = 60;
+
+/// Сколько секунд после последнего отчёта нода считается в сети: два интервала с запасом.
+pub fn online_window(interval: Duration) -> i64 {
+    2 * interval.as_secs() as i64 + ONLINE_GRACE_SECS
+}
+
+/// Откуда пришёл интервал.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// Измерен монитором этого процесса
+    Local,
+    /// Прислан агентом ноды: принимается в одной транзакции с начислением
+    Report,
+}
+
+/// Начисляет поинты за интервал по заданной политике и возвращает их количество.
+/// Лимит и пороги политики пересчитываются на фактическую длину интервала.
+/// Отчёт агента, перекрывающий уже принятый, отклоняется с `ReportOverlap`.
+#[tracing::instrument(
+    name = "interval",
+    skip_all,
+    fields(user = %usage.username, node = %usage.node_id, start = usage.interval_start, end = usage.interval_end)
+)]
+pub async fn credit_interval(
+    store: &dyn PointsStore,
+    policy: &ActivePolicy,
+    recent: &RecentUsage,
+    events: &EventBus,
+    source: Source,
+    usage: &IntervalUsage,
+) -> Result<i64, StoreError> {
+    let secs = (usage.interval_end - usage.interval_start).max(0) as u64;
+    let policy = &ActivePolicy { reward: policy.reward.for_interval(secs), earning_mode: policy.earning_mode };
+    let mode = policy.earning_mode;
+    let (upload, download) = (policy.reward.upload, policy.reward.download);
+    let billable = usage.billable(mode);
+    let earned = policy.reward.earned_points(billable.sent, billable.received);
+    if mode == EarningMode::IdleCapacity && usage.interfaces.iter().all(|i| i.capacity_bps.is_none()) {
+        tracing::warn!("Link capacity is unknown for all interfaces; set it with --link-capacity");
+    }
+
+    let idle = (mode == EarningMode::IdleCapacity).then_some(billable);
+    let entry = earned.map(|earned| NewTransaction {
+        username: usage.username.clone(),
+        node_id: Some(usage.node_id.clone()),
+        interval_start: Some(usage.interval_start),
+        interval_end: Some(usage.interval_end),
+        traffic: Some(TrafficDetails {
+            bytes_sent: usage.bytes_sent as i64,
+            bytes_received: usage.bytes_received as i64,
+            upload_threshold: upload.threshold as i64,
+            download_threshold: download.threshold as i64,
+            upload_points: earned.upload,
+            download_points: earned.download,
+            idle_bytes_sent: idle.map(|i| i.sent as i64),
+            idle_bytes_received: idle.map(|i| i.received as i64),
+        }),
+        points: earned.total,
+        reason: store::REASON_TRAFFIC.to_string(),
+        note: None,
+    });
+    // Атомарно начисляем поинты и пишем запись в журнал, без чтения текущего баланса
+    let new_points = match (source, &entry) {
+        (Source::Report, entry) => {
+            store.accept_node_report(&usage.node_id, usage.interval_start, usage.interval_end, entry.as_ref()).await?
+        }
+        (Source::Local, Some(entry)) => Some(store.credit_points(entry).await?),
+        (Source::Local, None) => None,
+    };
+
+    recent.record(usage);
+    // История нужна и для интервалов без начисления, а её сбой не должен лишать поинтов
+    if let Err(e) = store.record_traffic(&history::samples(usage)).await {
+        tracing::warn!(error = %e, "Failed to record traffic history");
+    }
+
+    let (Some(earned), Some(new_points)) = (earned, new_points) else {
+        tracing::info!(
+            ?mode,
+            sent = usage.bytes_sent,
+            received = usage.bytes_received,
+            billable_sent = billable.sent,
+            billable_received = billable.received,
+            upload_threshold = upload.threshold,
+            download_threshold = download.threshold,
+            "Not enough traffic to earn points"
+        );
+        publish(events, source, interval_event(usage, policy, billable, 0, None));
+        return Ok(0);
+    };
+    tracing::info!(
+        ?mode,
+        sent = usage.bytes_sent,
+        received = usage.bytes_received,
+        billable_sent = billable.sent,
+        billable_received = billable.received,
+        upload_threshold = upload.threshold,
+        download_threshold = download.threshold,
+        earned = earned.total,
+        upload_points = earned.upload,
+        download_points = earned.download,
+        total_points = new_points,
+        "Earned points"
+    );
+    publish(events, source, interval_event(usage, policy, billable, earned.total, Some(new_points)));
+    Ok(earned.total)
+}
+
+fn publish(events: &EventBus, source: Source, event: IntervalEvent) {
+    metrics::observe_interval(&event, source);
+    events.publish(event);
+}
+
+fn interval_event(
+    usage: &IntervalUsage,
+    policy: &ActivePolicy,
+    billable: Counters,
+    points: i64,
+    total_points: Option<i64>,
+) -> IntervalEvent {
+    IntervalEvent {
+        username: usage.username.clone(),
+        node_id: usage.node_id.clone(),
+        interval_start: usage.interval_start,
+        interval_end: usage.interval_end,
+        bytes_sent: usage.bytes_sent,
+        bytes_received: usage.bytes_received,
+        interfaces: usage.interfaces.clone(),
+        earning_mode: policy.earning_mode,
+        billable_sent: billable.sent,
+        billable_received: billable.received,
+        upload_threshold: policy.reward.upload.threshold,
+        download_threshold: policy.reward.download.threshold,
+        points,
+        total_points,
+    }
+}
+
+/// Время последнего завершённого прохода цикла каждого монитора и длина
+/// интервала, на котором он сейчас спит.
+#[derive(Default)]
+pub struct Heartbeats {
+    beats: Mutex<HashMap<String, Heartbeat>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Heartbeat {
+    at: i64,
+    interval: Duration,
+}
+
+impl Heartbeats {
+    // Монитор начал интервал: до этого интервала его не считают зависшим,
+    // даже если политику на ходу сократили
+    fn begin(&self, username: &str, interval: Duration) {
+        let mut beats = self.beats.lock().unwrap();
+        let beat = beats.entry(username.to_string()).or_insert(Heartbeat { at: store::unix_now(), interval });
+        beat.interval = interval;
+    }
+
+    fn beat(&self, username: &str) {
+        if let Some(beat) = self.beats.lock().unwrap().get_mut(username) {
+            beat.at = store::unix_now();
+        }
+    }
+
+    fn forget(&self, username: &str) {
+        self.beats.lock().unwrap().remove(username);
+    }
+}
+
+/// Состояние мониторов для проверки готовности.
+#[derive(Debug, Serialize)]
+pub struct MonitorHealth {
+    pub running: usize,
+    /// Мониторы, которые не завершали интервал дольше допустимого
+    pub stale: Vec<String>,
+    /// Мониторы, задача которых завершилась (паника); реестр перезапустит их
+    pub exited: Vec<String>,
+}
+
+impl MonitorHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.stale.is_empty() && self.exited.is_empty()
+    }
+}
+
+// Цикл мониторинга сетевого трафика и начисления поинтов для одного пользователя
+pub async fn monitor_network(
+    store: Arc<dyn PointsStore>,
+    config: Arc<Mutex<NodeConfig>>,
+    recent: Arc<RecentUsage>,
+    events: Arc<EventBus>,
+    heartbeats: Arc<Heartbeats>,
+    username: String,
+) {
+    let mut networks = Networks::new_with_refreshed_list();
+    let filter = config.lock().unwrap().interfaces.clone();
+    let mut meter = Meter::new(&NetworkUsage::new(&networks, &filter));
+    // Трафик, который ещё не удалось начислить: после сбоя базы он переходит в следующий интервал
+    let mut pending = NetworkUsage::default();
+    let mut interval_start = store::unix_now();
+
+    loop {
+        // Снимок политики берём в начале интервала: изменения на ходу
+        // вступают в силу со следующего интервала, а не посреди текущего
+        let policy = config.lock().unwrap().policy();
+        heartbeats.begin(&username, policy.reward.interval);
+        sleep(policy.reward.interval).await;
+
+        networks.refresh(true);
+        let (node_id, filter, capacities) = {
+            let config = config.lock().unwrap();
+            (config.node_id.clone(), config.interfaces.clone(), config.capacities.clone())
+        };
+        pending.accumulate(&meter.advance(&NetworkUsage::new(&networks, &filter)));
+        let usage = IntervalUsage {
+            username: username.clone(),
+            node_id,
+            interval_start,
+            interval_end: store::unix_now(),
+            bytes_sent: pending.sent(),
+            bytes_received: pending.received(),
+            interfaces: pending.interfaces(&capacities),
+        };
+
+        metrics().monitor_iterations.inc();
+        let credited = credit_interval(store.as_ref(), &policy, &recent, &events, Source::Local, &usage).await;
+        heartbeats.beat(&username);
+        if let Err(e) = credited {
+            metrics().monitor_failures.inc();
+            tracing::error!(user = %username, error = %e, "Failed to credit points, will retry with the next interval");
+            continue;
+        }
+
+        pending = NetworkUsage::default();
+        interval_start = usage.interval_end;
+    }
+}
+
+/// Реестр мониторов ноды. Счётчики интерфейсов общие для всего хоста, поэтому
+/// монитор один — для первого включённого аккаунта, явно привязанного к этой ноде.
+/// Привязки периодически сверяются с базой, так что новый, отключённый или удалённый
+/// владелец подхватывается без перезапуска.
+pub struct MonitorRegistry {
+    store: Arc<dyn PointsStore>,
+    config: Arc<Mutex<NodeConfig>>,
+    recent: Arc<RecentUsage>,
+    events: Arc<EventBus>,
+    heartbeats: Arc<Heartbeats>,
+    monitors: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl MonitorRegistry {
+    pub fn new(
+        store: Arc<dyn PointsStore>,
+        config: Arc<Mutex<NodeConfig>>,
+        recent: Arc<RecentUsage>,
+        events: Arc<EventBus>,
+    ) -> Self {
+        MonitorRegistry {
+            store,
+            config,
+            recent,
+            events,
+            heartbeats: Arc::new(Heartbeats::default()),
+            monitors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Запускает мониторы для новых аккаунтов и останавливает для исчезнувших.
+    pub async fn reconcile(&self) -> Result<(), StoreError> {
+        let node_id = self.config.lock().unwrap().node_id.clone();
+        let mut wanted = self.store.monitored_users(&node_id).await?;
+        // Один и тот же трафик нельзя оплатить нескольким аккаунтам: остальные привязки не действуют
+        let ignored = wanted.split_off(wanted.len().min(1));
+
+        let mut monitors = self.monitors.lock().unwrap();
+        monitors.retain(|username, handle| {
+            if handle.is_finished() {
+                // Упавший монитор перезапускается ниже, если аккаунт всё ещё активен
+                tracing::error!(user = %username, "Monitor exited unexpectedly");
+                self.heartbeats.forget(username);
+                return false;
+            }
+            if !wanted.contains(username) {
+                handle.abort();
+                self.heartbeats.forget(username);
+                tracing::info!(user = %username, "Stopped monitoring user");
+                return false;
+            }
+            true
+        });
+        for username in wanted {
+            if let Entry::Vacant(slot) = monitors.entry(username) {
+                let username = slot.key().clone();
+                tracing::info!(user = %username, node = %node_id, "Started monitoring user");
+                if !ignored.is_empty() {
+                    tracing::warn!(
+                        node = %node_id,
+                        ignored = ?ignored,
+                        "Several accounts are bound to this node; only the first one is credited"
+                    );
+                }
+                // Отсчёт до первого интервала идёт с момента запуска
+                self.heartbeats.beat(&username);
+                slot.insert(tokio::spawn(monitor_network(
+                    Arc::clone(&self.store),
+                    Arc::clone(&self.config),
+                    Arc::clone(&self.recent),
+                    Arc::clone(&self.events),
+                    Arc::clone(&self.heartbeats),
+                    username,
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Монитор считается зависшим, если не завершал интервал дольше `max_missed`
+    /// своих интервалов — тех, на которых он спит сейчас, а не текущих по политике.
+    pub fn health(&self, max_missed: u32) -> MonitorHealth {
+        let now = store::unix_now();
+        let monitors = self.monitors.lock().unwrap();
+        let beats = self.heartbeats.beats.lock().unwrap();
+        let mut health = MonitorHealth { running: 0, stale: Vec::new(), exited: Vec::new() };
+        for (username, handle) in monitors.iter() {
+            if handle.is_finished() {
+                health.exited.push(username.clone());
+            } else if beats
+                .get(username)
+                .is_none_or(|beat| now - beat.at > (beat.interval * max_missed).as_secs() as i64)
+            {
+                health.stale.push(username.clone());
+            } else {
+                health.running += 1;
+            }
+        }
+        health
+    }
+
+    /// Сверяет реестр с базой каждые `every`, пока процесс жив.
+    pub async fn run(self: Arc<Self>, every: Duration) {
+        loop {
+            if let Err(e) = self.reconcile().await {
+                tracing::warn!(error = %e, "Failed to refresh monitored users");
+            }
+            sleep(every).await;
+        }
+    }
+}
@@ -0,0 +1,398 @@
+// Снимки счётчиков сетевого трафика по интерфейсам
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use sysinfo::Networks;
+
+// Loopback, мосты контейнеров, veth-пары, виртуальные машины и VPN-туннели:
+// трафик через них либо локальный, либо уже учтён на физическом интерфейсе
+pub const DEFAULT_EXCLUDED_INTERFACES: &[&str] = &[
+    "lo", "lo0", "docker*", "br-*", "veth*", "virbr*", "vnet*", "vboxnet*", "vmnet*", "cni*",
+    "flannel*", "cali*", "kube-*", "tun*", "tap*", "wg*", "tailscale*", "zt*", "utun*",
+];
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid interface pattern '{pattern}': {source}")]
+pub struct FilterError {
+    pattern: String,
+    source: globset::Error,
+}
+
+/// Какие интерфейсы учитывать. Если задан список include, учитываются только
+/// совпавшие с ним; иначе все, кроме стандартных виртуальных. Список exclude
+/// применяется в любом случае.
+#[derive(Debug, Clone, Serialize)]
+pub struct InterfaceFilter {
+    // Исходные шаблоны — для журнала аудита
+    #[serde(rename = "include")]
+    include_patterns: Vec<String>,
+    #[serde(rename = "exclude")]
+    exclude_patterns: Vec<String>,
+    #[serde(skip)]
+    include: Option<GlobSet>,
+    #[serde(skip)]
+    exclude: GlobSet,
+    #[serde(skip)]
+    defaults: GlobSet,
+}
+
+fn build_set<S: AsRef<str>>(patterns: &[S]) -> Result<GlobSet, FilterError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        let glob = Glob::new(pattern).map_err(|source| FilterError { pattern: pattern.to_string(), source })?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|source| FilterError {
+        pattern: patterns.iter().map(|p| p.as_ref()).collect::<Vec<_>>().join(","),
+        source,
+    })
+}
+
+impl InterfaceFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, FilterError> {
+        Ok(InterfaceFilter {
+            include_patterns: include.to_vec(),
+            exclude_patterns: exclude.to_vec(),
+            include: if include.is_empty() { None } else { Some(build_set(include)?) },
+            exclude: build_set(exclude)?,
+            defaults: build_set(DEFAULT_EXCLUDED_INTERFACES)?,
+        })
+    }
+
+    pub fn matches(&self, interface: &str) -> bool {
+        if self.exclude.is_match(interface) {
+            return false;
+        }
+        match &self.include {
+            Some(include) => include.is_match(interface),
+            None => !self.defaults.is_match(interface),
+        }
+    }
+}
+
+impl Default for InterfaceFilter {
+    fn default() -> Self {
+        InterfaceFilter::new(&[], &[]).expect("default interface patterns are valid")
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid link capacity '{0}', expected INTERFACE=MBITS with MBITS > 0")]
+pub struct CapacityError(String);
+
+/// Пропускная способность интерфейсов в бит/с. Заданная вручную имеет приоритет,
+/// для остальных интерфейсов скорость линка берётся из ОС.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct LinkCapacities {
+    overrides: BTreeMap<String, u64>,
+}
+
+impl LinkCapacities {
+    /// Разбирает значения вида `eth0=1000` (Мбит/с).
+    pub fn new(specs: &[String]) -> Result<Self, CapacityError> {
+        let mut overrides = BTreeMap::new();
+        for spec in specs {
+            let (name, mbits) = spec
+                .split_once('=')
+                .and_then(|(name, mbits)| Some((name.trim(), mbits.trim().parse::<u64>().ok()?)))
+                .filter(|(name, mbits)| !name.is_empty() && *mbits > 0)
+                .ok_or_else(|| CapacityError(spec.clone()))?;
+            overrides.insert(name.to_string(), mbits.saturating_mul(1_000_000));
+        }
+        Ok(LinkCapacities { overrides })
+    }
+
+    pub fn capacity_bps(&self, interface: &str) -> Option<u64> {
+        self.overrides.get(interface).copied().or_else(|| detect_link_speed(interface))
+    }
+}
+
+/// Скорость линка по данным ядра. Для виртуальных, беспроводных и отключённых
+/// интерфейсов Linux её не сообщает (файла нет или в нём -1).
+fn detect_link_speed(interface: &str) -> Option<u64> {
+    // Имя интерфейса подставляется в путь — отсекаем всё, что может из него выйти
+    if interface.is_empty() || interface.contains('/') || interface.starts_with('.') {
+        return None;
+    }
+    let speed = fs::read_to_string(format!("/sys/class/net/{}/speed", interface)).ok()?;
+    let mbits = speed.trim().parse::<i64>().ok().filter(|mbits| *mbits > 0)?;
+    Some((mbits as u64).saturating_mul(1_000_000))
+}
+
+/// Счётчики одного интерфейса.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// Трафик одного интерфейса за интервал — в таком виде он уходит в отчёты и API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterfaceUsage {
+    pub name: String,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Пропускная способность линка в бит/с, если известна
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity_bps: Option<u64>,
+}
+
+/// Неиспользованная ёмкость линков за интервал длиной `secs` секунд: сколько
+/// байт ещё можно было передать в каждом направлении. Линки полнодуплексные,
+/// так что ёмкость у направлений своя. Интерфейсы с неизвестной скоростью
+/// не учитываются.
+pub fn idle_capacity(interfaces: &[InterfaceUsage], secs: u64) -> Counters {
+    interfaces
+        .iter()
+        .filter_map(|usage| {
+            let capacity = (usage.capacity_bps? / 8).saturating_mul(secs);
+            Some(Counters {
+                sent: capacity.saturating_sub(usage.bytes_sent),
+                received: capacity.saturating_sub(usage.bytes_received),
+            })
+        })
+        .fold(Counters::default(), |total, idle| Counters {
+            sent: total.sent.saturating_add(idle.sent),
+            received: total.received.saturating_add(idle.received),
+        })
+}
+
+/// Снимок счётчиков (или трафик за интервал) по интерфейсам.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkUsage {
+    interfaces: BTreeMap<String, Counters>,
+}
+
+impl FromIterator<(String, Counters)> for NetworkUsage {
+    fn from_iter<I: IntoIterator<Item = (String, Counters)>>(iter: I) -> Self {
+        NetworkUsage { interfaces: iter.into_iter().collect() }
+    }
+}
+
+impl NetworkUsage {
+    pub fn new(networks: &Networks, filter: &InterfaceFilter) -> Self {
+        networks
+            .iter()
+            .filter(|(name, _)| filter.matches(name))
+            .map(|(name, network)| {
+                let counters = Counters {
+                    sent: network.total_transmitted(),
+                    received: network.total_received(),
+                };
+                (name.clone(), counters)
+            })
+            .collect()
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.interfaces.values().map(|c| c.sent).sum()
+    }
+
+    pub fn received(&self) -> u64 {
+        self.interfaces.values().map(|c| c.received).sum()
+    }
+
+    /// Прибавляет трафик другого интервала — агент так копит неотправленные отчёты.
+    pub fn accumulate(&mut self, other: &NetworkUsage) {
+        for (name, delta) in &other.interfaces {
+            let counters = self.interfaces.entry(name.clone()).or_default();
+            counters.sent = counters.sent.saturating_add(delta.sent);
+            counters.received = counters.received.saturating_add(delta.received);
+        }
+    }
+
+    pub fn interfaces(&self, capacities: &LinkCapacities) -> Vec<InterfaceUsage> {
+        self.interfaces
+            .iter()
+            .map(|(name, c)| InterfaceUsage {
+                name: name.clone(),
+                bytes_sent: c.sent,
+                bytes_received: c.received,
+                capacity_bps: capacities.capacity_bps(name),
+            })
+            .collect()
+    }
+}
+
+// Разрядность счётчика ядро не сообщает. Переполнением 32-битного счётчика считаем
+// уменьшение только с самого верха диапазона: 64-битный счётчик, сброшенный на
+// 2–4 ГиБ, иначе насчитал бы сотни мегабайт несуществующего трафика.
+const WRAP_WINDOW: u64 = 256 * 1024 * 1024;
+
+// Самое большое приращение, которое ещё считается переполнением 32-битного счётчика,
+// а не его сбросом
+const MAX_WRAP_DELTA: u64 = (u32::MAX / 2) as u64;
+
+/// Приращение счётчика между двумя замерами.
+///
+/// Если счётчик уменьшился, это либо переполнение 32-битного счётчика (старые
+/// драйверы и 32-битные ядра), либо сброс — интерфейс пересоздан или драйвер
+/// перезагружен. После сброса счётчик считает с нуля, поэтому его текущее
+/// значение и есть трафик с момента сброса. В сомнительных случаях выбирается
+/// сброс: он недосчитывает трафик, а не приписывает лишний.
+pub fn counter_delta(previous: u64, current: u64) -> u64 {
+    if current >= previous {
+        return current - previous;
+    }
+    if previous <= u32::MAX as u64 && u32::MAX as u64 - previous < WRAP_WINDOW {
+        let wrapped = (u32::MAX as u64 - previous) + current + 1;
+        if wrapped <= MAX_WRAP_DELTA {
+            return wrapped;
+        }
+    }
+    current
+}
+
+/// Считает трафик за интервалы по последовательным снимкам счётчиков.
+///
+/// Каждый интерфейс считается отдельно, поэтому сброс или исчезновение одного
+/// не влияет на остальные. Последние значения исчезнувших интерфейсов
+/// запоминаются: если интерфейс вернётся, его трафик посчитается от них (или от
+/// нуля, если счётчик сброшен). Интерфейс, которого раньше не было, в первом
+/// интервале только запоминается — его счётчик мог накопиться до начала учёта.
+#[derive(Debug, Default)]
+pub struct Meter {
+    last: BTreeMap<String, Counters>,
+}
+
+impl Meter {
+    pub fn new(baseline: &NetworkUsage) -> Self {
+        Meter { last: baseline.interfaces.clone() }
+    }
+
+    /// Принимает новый снимок и возвращает трафик с предыдущего по интерфейсам.
+    pub fn advance(&mut self, snapshot: &NetworkUsage) -> NetworkUsage {
+        let mut delta = NetworkUsage::default();
+        for (name, current) in &snapshot.interfaces {
+            let counters = match self.last.insert(name.clone(), *current) {
+                Some(previous) => Counters {
+                    sent: counter_delta(previous.sent, current.sent),
+                    received: counter_delta(previous.received, current.received),
+                },
+                None => Counters::default(),
+            };
+            delta.interfaces.insert(name.clone(), counters);
+        }
+        delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(interfaces: &[(&str, u64, u64)]) -> NetworkUsage {
+        interfaces
+            .iter()
+            .map(|&(name, sent, received)| (name.to_string(), Counters { sent, received }))
+            .collect()
+    }
+
+    #[test]
+    fn counts_plain_increase() {
+        assert_eq!(counter_delta(100, 250), 150);
+        assert_eq!(counter_delta(100, 100), 0);
+    }
+
+    #[test]
+    fn counts_32bit_wraparound() {
+        let previous = u32::MAX as u64 - 99;
+        assert_eq!(counter_delta(previous, 400), 500);
+    }
+
+    #[test]
+    fn counts_reset_as_traffic_since_reset() {
+        // Маленький 32-битный счётчик: переполнением это быть не может
+        assert_eq!(counter_delta(1_000_000, 10), 10);
+        // 64-битный счётчик не переполняется, только сбрасывается
+        assert_eq!(counter_delta(10_000_000_000, 4_096), 4_096);
+        // Сброс 64-битного счётчика со значения между 2 и 4 ГиБ — не переполнение
+        assert_eq!(counter_delta(3_500_000_000, 0), 0);
+        assert_eq!(counter_delta(2_500_000_000, 1_000), 1_000);
+    }
+
+    #[test]
+    fn meter_tracks_interfaces_independently() {
+        let mut meter = Meter::new(&usage(&[("eth0", 1_000, 2_000), ("wlan0", 500, 500)]));
+
+        // eth0 сбросился, wlan0 продолжает расти — его трафик не теряется
+        let delta = meter.advance(&usage(&[("eth0", 300, 100), ("wlan0", 1_500, 900)]));
+        assert_eq!(delta, usage(&[("eth0", 300, 100), ("wlan0", 1_000, 400)]));
+        assert_eq!((delta.sent(), delta.received()), (1_300, 500));
+    }
+
+    #[test]
+    fn meter_ignores_removed_interface() {
+        let mut meter = Meter::new(&usage(&[("eth0", 1_000, 1_000), ("tun0", 9_000, 9_000)]));
+
+        let delta = meter.advance(&usage(&[("eth0", 1_200, 1_100)]));
+        assert_eq!(delta, usage(&[("eth0", 200, 100)]));
+    }
+
+    #[test]
+    fn meter_resumes_interface_that_came_back() {
+        let mut meter = Meter::new(&usage(&[("eth0", 0, 0), ("usb0", 5_000, 7_000)]));
+        meter.advance(&usage(&[("eth0", 10, 10)]));
+
+        // Вернулся с продолжившимися счётчиками
+        let delta = meter.advance(&usage(&[("eth0", 20, 20), ("usb0", 5_600, 7_100)]));
+        assert_eq!(delta, usage(&[("eth0", 10, 10), ("usb0", 600, 100)]));
+
+        // Пропал и вернулся со счётчиками с нуля
+        meter.advance(&usage(&[("eth0", 30, 30)]));
+        let delta = meter.advance(&usage(&[("eth0", 40, 40), ("usb0", 250, 80)]));
+        assert_eq!(delta, usage(&[("eth0", 10, 10), ("usb0", 250, 80)]));
+    }
+
+    #[test]
+    fn meter_baselines_hotplugged_interface() {
+        let mut meter = Meter::new(&usage(&[("eth0", 100, 100)]));
+
+        // Новый интерфейс с накопленным до начала учёта счётчиком
+        let delta = meter.advance(&usage(&[("eth0", 150, 120), ("usb0", 80_000, 90_000)]));
+        assert_eq!(delta, usage(&[("eth0", 50, 20), ("usb0", 0, 0)]));
+
+        let delta = meter.advance(&usage(&[("eth0", 150, 120), ("usb0", 80_500, 90_700)]));
+        assert_eq!(delta, usage(&[("eth0", 0, 0), ("usb0", 500, 700)]));
+    }
+
+    #[test]
+    fn accumulate_sums_intervals() {
+        let mut pending = usage(&[("eth0", 10, 20)]);
+        pending.accumulate(&usage(&[("eth0", 5, 5), ("wlan0", 1, 2)]));
+        assert_eq!(pending, usage(&[("eth0", 15, 25), ("wlan0", 1, 2)]));
+    }
+
+    #[test]
+    fn parses_link_capacity_overrides() {
+        let capacities = LinkCapacities::new(&["eth0=1000".to_string(), " wlan0 = 300".to_string()]).unwrap();
+        assert_eq!(capacities.capacity_bps("eth0"), Some(1_000_000_000));
+        assert_eq!(capacities.capacity_bps("wlan0"), Some(300_000_000));
+
+        for spec in ["eth0", "eth0=", "=100", "eth0=0", "eth0=fast"] {
+            assert!(LinkCapacities::new(&[spec.to_string()]).is_err(), "{spec}");
+        }
+    }
+
+    #[test]
+    fn idle_capacity_is_headroom_per_direction() {
+        let link = |name: &str, sent, received, capacity_bps| InterfaceUsage {
+            name: name.to_string(),
+            bytes_sent: sent,
+            bytes_received: received,
+            capacity_bps,
+        };
+        // 8 Мбит/с за 10 секунд — 10 МБ в каждую сторону
+        let interfaces = [
+            link("eth0", 4_000_000, 10_000_000, Some(8_000_000)),
+            link("wlan0", 1_000, 1_000, None),
+            // Счётчики могут обогнать номинал (агрегация, неточная скорость) — не уходим в минус
+            link("usb0", 2_000_000, 0, Some(800_000)),
+        ];
+        let idle = idle_capacity(&interfaces, 10);
+        assert_eq!(idle, Counters { sent: 6_000_000, received: 1_000_000 });
+    }
+}
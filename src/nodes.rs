@@ -0,0 +1,111 @@
+// Серверная часть протокола агентов: регистрация нод и приём подписанных отчётов
+use crate::auth::AuthUser;
+use crate::config::NodeConfig;
+use crate::error::ApiError;
+use crate::events::EventBus;
+use crate::monitor::{credit_interval, IntervalUsage, RecentUsage, Source};
+use crate::report::{self, EnrollRequest, NodeCredentials, UsageReport};
+use crate::store::{self, Enrollment, PointsStore};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+
+/// Токен, которым агенты подтверждают право регистрировать ноды.
+/// Без токена регистрация на сервере выключена.
+pub struct EnrollmentToken(pub Option<String>);
+
+// Регистрация ноды: выдаёт агенту секрет для подписи отчётов
+pub async fn enroll_node(
+    user: AuthUser,
+    store: web::Data<Arc<dyn PointsStore>>,
+    enrollment: web::Data<EnrollmentToken>,
+    request: web::Json<EnrollRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(expected) = enrollment.0.as_deref() else {
+        return Err(ApiError::Forbidden("node enrollment is disabled on this server"));
+    };
+    if !bool::from(expected.as_bytes().ct_eq(request.token.as_bytes())) {
+        return Err(ApiError::Unauthorized("invalid enrollment token"));
+    }
+    report::validate_node_id(&request.node_id).map_err(ApiError::bad_request)?;
+
+    let username = request.username.clone().unwrap_or_else(|| user.username.clone());
+    if !user.can_enroll(&username, &request.node_id) {
+        return Err(ApiError::Forbidden("you can only enroll nodes for your own account"));
+    }
+
+    let secret = report::generate_secret();
+    match store.create_node(&request.node_id, &username, &secret).await? {
+        Enrollment::Created => {}
+        Enrollment::NodeTaken => return Err(ApiError::Conflict("node is already enrolled")),
+        Enrollment::AccountBound => return Err(ApiError::Conflict("account is already bound to a node")),
+    }
+    tracing::info!(node = %request.node_id, user = %username, "Enrolled node");
+    Ok(HttpResponse::Created().json(NodeCredentials { node_id: request.node_id.clone(), secret }))
+}
+
+// Приём отчёта о трафике: проверка подписи или API-ключа, защита от повторов и начисление
+pub async fn submit_report(
+    req: HttpRequest,
+    body: web::Bytes,
+    node_id: web::Path<String>,
+    store: web::Data<Arc<dyn PointsStore>>,
+    config: web::Data<Arc<Mutex<NodeConfig>>>,
+    recent: web::Data<Arc<RecentUsage>>,
+    events: web::Data<Arc<EventBus>>,
+) -> Result<HttpResponse, ApiError> {
+    let Some(node) = store.get_node(&node_id).await? else {
+        return Err(ApiError::Unauthorized("unknown node or invalid signature"));
+    };
+
+    // Отчёт подписан либо секретом ноды, либо API-ключом её владельца
+    let by_api_key = req.extensions().get::<AuthUser>().is_some_and(|user| user.can_submit_for(&node));
+    let now = store::unix_now();
+    if !by_api_key {
+        let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+        let (Some(timestamp), Some(signature)) = (
+            header(report::TIMESTAMP_HEADER).and_then(|v| v.parse::<i64>().ok()),
+            header(report::SIGNATURE_HEADER),
+        ) else {
+            return Err(ApiError::Unauthorized("missing timestamp or signature"));
+        };
+        if header(report::NODE_ID_HEADER) != Some(node_id.as_str()) {
+            return Err(ApiError::Unauthorized("node id header does not match the url"));
+        }
+        if !report::verify(&node.secret, timestamp, &body, signature) {
+            return Err(ApiError::Unauthorized("unknown node or invalid signature"));
+        }
+        if (now - timestamp).abs() > report::MAX_CLOCK_SKEW {
+            return Err(ApiError::Unauthorized("report timestamp is too far from server time"));
+        }
+    }
+    let usage_report: UsageReport =
+        serde_json::from_slice(&body).map_err(|e| ApiError::bad_request(format!("invalid report: {}", e)))?;
+    if usage_report.interval_end <= usage_report.interval_start
+        || usage_report.interval_end > now + report::MAX_CLOCK_SKEW
+    {
+        return Err(ApiError::bad_request("invalid report interval"));
+    }
+    usage_report.validate_interfaces().map_err(ApiError::bad_request)?;
+    // Поинты пропорциональны длине интервала, поэтому он не может начинаться до регистрации ноды
+    if usage_report.interval_start < node.enrolled_at - report::MAX_CLOCK_SKEW {
+        return Err(ApiError::bad_request("report interval starts before the node was enrolled"));
+    }
+    if !node.user_enabled {
+        return Err(ApiError::Forbidden("account is disabled"));
+    }
+
+    let usage = IntervalUsage {
+        username: node.username,
+        node_id: node.id,
+        interval_start: usage_report.interval_start,
+        interval_end: usage_report.interval_end,
+        bytes_sent: usage_report.bytes_sent,
+        bytes_received: usage_report.bytes_received,
+        interfaces: usage_report.interfaces,
+    };
+    let policy = config.lock().unwrap().policy();
+    // Отметка отчёта и начисление проходят вместе: после сбоя агент повторит тот же интервал
+    let points = credit_interval(store.get_ref().as_ref(), &policy, &recent, &events, Source::Report, &usage).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "credited_points": points })))
+}
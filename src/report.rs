@@ -0,0 +1,177 @@
+// Протокол между агентом и сервером: регистрация ноды и подписанные отчёты о трафике
+use crate::network::InterfaceUsage;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+pub const NODE_ID_HEADER: &str = "X-Node-Id";
+pub const TIMESTAMP_HEADER: &str = "X-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Допустимое расхождение часов агента и сервера, секунд.
+pub const MAX_CLOCK_SKEW: i64 = 300;
+
+/// Сколько интерфейсов может быть в разбивке одного отчёта.
+pub const MAX_REPORT_INTERFACES: usize = 64;
+/// Самая большая ёмкость линка, которую принимаем от агента: 10 Тбит/с.
+pub const MAX_CAPACITY_BPS: u64 = 10_000_000_000_000;
+const MAX_NAME_LEN: usize = 64;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Запрос на регистрацию ноды. Нода регистрируется за пользователем, чьей сессией
+/// или API-ключом подписан запрос; `username` может указать только администратор.
+#[derive(Serialize, Deserialize)]
+pub struct EnrollRequest {
+    pub node_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    pub token: String,
+}
+
+/// Учётные данные ноды: выдаются при регистрации и хранятся у агента в файле.
+#[derive(Serialize, Deserialize)]
+pub struct NodeCredentials {
+    pub node_id: String,
+    pub secret: String,
+}
+
+/// Трафик ноды за интервал `[interval_start, interval_end)`.
+#[derive(Serialize, Deserialize)]
+pub struct UsageReport {
+    pub interval_start: i64,
+    pub interval_end: i64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Разбивка по интерфейсам; старые агенты её не присылают
+    #[serde(default)]
+    pub interfaces: Vec<InterfaceUsage>,
+}
+
+/// Id ноды: 1–64 символа из латиницы, цифр, `_`, `-` и `.` — как у имени хоста.
+pub fn validate_node_id(node_id: &str) -> Result<(), &'static str> {
+    if node_id.is_empty() || node_id.len() > MAX_NAME_LEN {
+        return Err("node_id must be 1 to 64 characters long");
+    }
+    if !node_id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+        return Err("node_id may contain only latin letters, digits, '_', '-' and '.'");
+    }
+    Ok(())
+}
+
+impl UsageReport {
+    /// Разбивка по интерфейсам сохраняется в историю трафика и по ней считается
+    /// простаивающая ёмкость, поэтому её размер и имена ограничены, одно имя не
+    /// встречается дважды, а сумма по интерфейсам совпадает с итогом отчёта.
+    pub fn validate_interfaces(&self) -> Result<(), &'static str> {
+        if self.interfaces.len() > MAX_REPORT_INTERFACES {
+            return Err("report lists too many interfaces");
+        }
+        if !self.interfaces.is_empty() {
+            let sum = |bytes: fn(&InterfaceUsage) -> u64| {
+                self.interfaces.iter().try_fold(0u64, |total, interface| total.checked_add(bytes(interface)))
+            };
+            if sum(|i| i.bytes_sent) != Some(self.bytes_sent) || sum(|i| i.bytes_received) != Some(self.bytes_received) {
+                return Err("interface breakdown does not add up to the report totals");
+            }
+        }
+        let mut names = std::collections::HashSet::new();
+        for interface in &self.interfaces {
+            let name = interface.name.as_str();
+            if name.is_empty() || name.len() > MAX_NAME_LEN || name.chars().any(char::is_control) {
+                return Err("interface names must be 1 to 64 printable characters");
+            }
+            if !names.insert(name) {
+                return Err("report lists the same interface twice");
+            }
+            if interface.capacity_bps.is_some_and(|bps| bps > MAX_CAPACITY_BPS) {
+                return Err("interface capacity is out of range");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Новый секрет ноды: 32 случайных байта в hex.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b"\n");
+    mac.update(body);
+    mac
+}
+
+/// Подпись тела отчёта: hex(HMAC-SHA256(secret, "<timestamp>\n<body>")).
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    hex::encode(mac(secret, timestamp, body).finalize().into_bytes())
+}
+
+/// Проверяет подпись за постоянное время.
+pub fn verify(secret: &str, timestamp: i64, body: &[u8], signature: &str) -> bool {
+    match hex::decode(signature) {
+        Ok(signature) => mac(secret, timestamp, body).verify_slice(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(name: &str, sent: u64, received: u64) -> InterfaceUsage {
+        InterfaceUsage { name: name.to_string(), bytes_sent: sent, bytes_received: received, capacity_bps: None }
+    }
+
+    fn report(interfaces: Vec<InterfaceUsage>) -> UsageReport {
+        UsageReport { interval_start: 0, interval_end: 30, bytes_sent: 300, bytes_received: 700, interfaces }
+    }
+
+    #[test]
+    fn validates_interface_breakdown() {
+        assert!(report(Vec::new()).validate_interfaces().is_ok());
+        assert!(report(vec![interface("eth0", 100, 700), interface("wlan0", 200, 0)]).validate_interfaces().is_ok());
+
+        // Оплачен итог, а в историю ушла бы разбивка: они должны совпадать
+        assert!(report(vec![interface("eth0", 100, 700)]).validate_interfaces().is_err());
+        assert!(report(vec![interface("eth0", u64::MAX, 700), interface("eth1", 301, 0)]).validate_interfaces().is_err());
+        assert!(report(vec![interface("eth0", 100, 350), interface("eth0", 200, 350)]).validate_interfaces().is_err());
+        assert!(report(vec![interface("", 300, 700)]).validate_interfaces().is_err());
+        assert!(report(vec![interface("eth\n0", 300, 700)]).validate_interfaces().is_err());
+
+        let mut fast = interface("eth0", 300, 700);
+        fast.capacity_bps = Some(MAX_CAPACITY_BPS + 1);
+        assert!(report(vec![fast]).validate_interfaces().is_err());
+
+        let many = (0..=MAX_REPORT_INTERFACES).map(|i| interface(&format!("eth{}", i), 0, 0)).collect();
+        assert!(UsageReport { bytes_sent: 0, bytes_received: 0, ..report(many) }.validate_interfaces().is_err());
+    }
+
+    #[test]
+    fn signature_covers_body_and_timestamp() {
+        let body = br#"{"interval_start":0,"interval_end":30,"bytes_sent":1,"bytes_received":2}"#;
+        let signature = sign("secret", 1_700_000_000, body);
+        assert!(verify("secret", 1_700_000_000, body, &signature));
+
+        assert!(!verify("secret", 1_700_000_001, body, &signature));
+        assert!(!verify("other", 1_700_000_000, body, &signature));
+        let tampered = String::from_utf8_lossy(body).replace("\"bytes_sent\":1", "\"bytes_sent\":9");
+        assert!(!verify("secret", 1_700_000_000, tampered.as_bytes(), &signature));
+        assert!(!verify("secret", 1_700_000_000, body, "not hex"));
+        assert!(!verify("secret", 1_700_000_000, body, &signature[..32]));
+    }
+
+    #[test]
+    fn validates_node_ids() {
+        assert!(validate_node_id("edge-01.example").is_ok());
+        assert!(validate_node_id("").is_err());
+        assert!(validate_node_id("bad node/x").is_err());
+        assert!(validate_node_id(&"n".repeat(65)).is_err());
+    }
+}
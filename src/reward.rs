@@ -0,0 +1,227 @@
+// Политика начисления: сколько поинтов даёт трафик за интервал
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Как округлять дробные поинты по каждому направлению.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Rounding {
+    Floor,
+    Round,
+    Ceil,
+}
+
+impl Rounding {
+    fn apply(self, value: f64) -> i64 {
+        match self {
+            Rounding::Floor => value.floor() as i64,
+            Rounding::Round => value.round() as i64,
+            Rounding::Ceil => value.ceil() as i64,
+        }
+    }
+}
+
+/// За что нода получает поинты.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum EarningMode {
+    /// За трафик, который реально прошёл через интерфейсы
+    #[default]
+    Contributed,
+    /// За незанятую часть пропускной способности линков
+    IdleCapacity,
+}
+
+/// Порог и ставка для одного направления трафика.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DirectionRate {
+    /// Байт за интервал, которые не оплачиваются
+    pub threshold: u64,
+    /// Поинтов за каждый байт сверх порога
+    pub rate: f64,
+}
+
+/// Поинты за интервал по направлениям.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct EarnedPoints {
+    pub upload: i64,
+    pub download: i64,
+    pub total: i64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("{0} rate must be a finite number >= 0, got {1}")]
+    InvalidRate(&'static str, f64),
+    #[error("at least one of upload and download rates must be positive")]
+    NothingEarns,
+    #[error("points cap must be at least 1, got {0}")]
+    InvalidCap(i64),
+    #[error("interval must be between {min} and {max} seconds, got {0}", min = MIN_INTERVAL_SECS, max = MAX_INTERVAL_SECS)]
+    InvalidInterval(u64),
+}
+
+const MIN_INTERVAL_SECS: u64 = 5;
+const MAX_INTERVAL_SECS: u64 = 86_400;
+
+/// Экономика фарминга: пороги и ставки по направлениям, лимит за интервал,
+/// округление и длина интервала измерений.
+#[derive(Debug, Clone, Serialize)]
+pub struct RewardPolicy {
+    /// Исходящий трафик — то, чем нода делится с сетью
+    pub upload: DirectionRate,
+    /// Входящий трафик
+    pub download: DirectionRate,
+    /// Не больше стольких поинтов за один интервал
+    pub cap: i64,
+    pub rounding: Rounding,
+    #[serde(rename = "interval_secs", serialize_with = "serialize_secs")]
+    pub interval: Duration,
+}
+
+fn serialize_secs<S: serde::Serializer>(interval: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(interval.as_secs())
+}
+
+impl Default for RewardPolicy {
+    // Прежние зашитые значения: порог 1024 байта, 1 поинт за 1.5 байта, не больше 10 за 30 секунд
+    fn default() -> Self {
+        let direction = DirectionRate { threshold: 1024, rate: 1.0 / 1.5 };
+        RewardPolicy {
+            upload: direction,
+            download: direction,
+            cap: 10,
+            rounding: Rounding::Floor,
+            interval: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RewardPolicy {
+    /// Проверяет политику целиком; вызывается при старте до запуска мониторов.
+    pub fn validate(&self) -> Result<(), PolicyError> {
+        for (name, direction) in [("upload", &self.upload), ("download", &self.download)] {
+            if !direction.rate.is_finite() || direction.rate < 0.0 {
+                return Err(PolicyError::InvalidRate(name, direction.rate));
+            }
+        }
+        if self.upload.rate == 0.0 && self.download.rate == 0.0 {
+            return Err(PolicyError::NothingEarns);
+        }
+        if self.cap < 1 {
+            return Err(PolicyError::InvalidCap(self.cap));
+        }
+        let secs = self.interval.as_secs();
+        if !(MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&secs) {
+            return Err(PolicyError::InvalidInterval(secs));
+        }
+        Ok(())
+    }
+
+    /// Политика для интервала длиной `secs`: лимит и пороги пропорциональны его длине.
+    /// Так нода получает за единицу времени одинаково, дробит ли она трафик на короткие
+    /// отчёты или присылает один за весь период, пока была недоступна.
+    pub fn for_interval(&self, secs: u64) -> RewardPolicy {
+        let interval = self.interval.as_secs().max(1) as u128;
+        // Порог округляется вверх, лимит — вниз: короткий интервал не даёт больше положенного
+        let threshold =
+            |threshold: u64| (threshold as u128 * secs as u128).div_ceil(interval).min(u64::MAX as u128) as u64;
+        RewardPolicy {
+            upload: DirectionRate { threshold: threshold(self.upload.threshold), ..self.upload },
+            download: DirectionRate { threshold: threshold(self.download.threshold), ..self.download },
+            cap: (self.cap as u128 * secs as u128 / interval).min(i64::MAX as u128) as i64,
+            rounding: self.rounding,
+            interval: Duration::from_secs(secs),
+        }
+    }
+
+    fn earned(&self, direction: &DirectionRate, bytes: u64) -> Option<i64> {
+        if bytes <= direction.threshold {
+            return None;
+        }
+        Some(self.rounding.apply((bytes - direction.threshold) as f64 * direction.rate))
+    }
+
+    /// Поинты за интервал: по каждому направлению (bytes - threshold) * rate с округлением,
+    /// в сумме не больше `cap`. Лимит в первую очередь заполняется исходящим трафиком.
+    /// `None`, если начислять нечего: ни одно направление не превысило порог
+    /// или после округления и лимита получился ноль.
+    pub fn earned_points(&self, bytes_sent: u64, bytes_received: u64) -> Option<EarnedPoints> {
+        let upload = self.earned(&self.upload, bytes_sent).unwrap_or(0).min(self.cap);
+        let download = self.earned(&self.download, bytes_received).unwrap_or(0).min(self.cap - upload);
+        let total = upload + download;
+        (total > 0).then_some(EarnedPoints { upload, download, total })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(rate: f64, cap: i64, rounding: Rounding) -> RewardPolicy {
+        let direction = DirectionRate { threshold: 100, rate };
+        RewardPolicy { upload: direction, download: direction, cap, rounding, ..Default::default() }
+    }
+
+    #[test]
+    fn pays_only_above_threshold() {
+        let policy = policy(1.0, 1000, Rounding::Floor);
+        assert!(policy.earned_points(100, 100).is_none());
+        let earned = policy.earned_points(101, 100).unwrap();
+        assert_eq!((earned.upload, earned.download, earned.total), (1, 0, 1));
+        let earned = policy.earned_points(0, 103).unwrap();
+        assert_eq!((earned.upload, earned.download, earned.total), (0, 3, 3));
+    }
+
+    #[test]
+    fn rounds_each_direction() {
+        // 3 байта сверх порога по 0.5 поинта — 1.5 поинта до округления
+        let earned = |rounding| policy(0.5, 1000, rounding).earned_points(103, 0).map(|e| e.total);
+        assert_eq!(earned(Rounding::Floor), Some(1));
+        assert_eq!(earned(Rounding::Round), Some(2));
+        assert_eq!(earned(Rounding::Ceil), Some(2));
+        // Порог превышен, но после округления начислять нечего — записи в журнале не будет
+        assert!(policy(0.5, 1000, Rounding::Floor).earned_points(101, 101).is_none());
+        let mut upload_only = policy(1.0, 1000, Rounding::Floor);
+        upload_only.download.rate = 0.0;
+        assert!(upload_only.earned_points(0, 5000).is_none());
+    }
+
+    #[test]
+    fn cap_is_filled_by_upload_first() {
+        let policy = policy(1.0, 10, Rounding::Floor);
+        let earned = policy.earned_points(106, 110).unwrap();
+        assert_eq!((earned.upload, earned.download, earned.total), (6, 4, 10));
+        let earned = policy.earned_points(200, 200).unwrap();
+        assert_eq!((earned.upload, earned.download, earned.total), (10, 0, 10));
+    }
+
+    #[test]
+    fn scales_cap_and_thresholds_with_interval_length() {
+        let policy = RewardPolicy::default();
+        // Десять секундных отчётов не дают больше одного интервала в 30 секунд
+        let second = policy.for_interval(1);
+        assert_eq!((second.cap, second.upload.threshold), (0, 35));
+        assert!(second.earned_points(u64::MAX, u64::MAX).is_none());
+
+        let same = policy.for_interval(30);
+        assert_eq!((same.cap, same.upload.threshold, same.download.threshold), (10, 1024, 1024));
+        // Отчёт за час простоя агента оплачивается как 120 интервалов
+        let hour = policy.for_interval(3600);
+        assert_eq!((hour.cap, hour.upload.threshold), (1200, 122_880));
+        assert_eq!(hour.earned_points(u64::MAX, 0).unwrap().total, 1200);
+    }
+
+    #[test]
+    fn validates_policy() {
+        assert!(RewardPolicy::default().validate().is_ok());
+        assert!(matches!(policy(-1.0, 10, Rounding::Floor).validate(), Err(PolicyError::InvalidRate("upload", _))));
+        assert!(matches!(policy(f64::NAN, 10, Rounding::Floor).validate(), Err(PolicyError::InvalidRate(..))));
+        assert!(matches!(policy(0.0, 10, Rounding::Floor).validate(), Err(PolicyError::NothingEarns)));
+        assert!(matches!(policy(1.0, 0, Rounding::Floor).validate(), Err(PolicyError::InvalidCap(0))));
+        for secs in [MIN_INTERVAL_SECS - 1, MAX_INTERVAL_SECS + 1] {
+            let policy = RewardPolicy { interval: Duration::from_secs(secs), ..Default::default() };
+            assert!(matches!(policy.validate(), Err(PolicyError::InvalidInterval(got)) if got == secs));
+        }
+    }
+}
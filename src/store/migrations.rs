@@ -0,0 +1,319 @@
+// Встроенные миграции схемы. Каждая миграция выполняется один раз и
+// записывается в schema_migrations; порядок определяется номером версии.
+use super::{PointsStore, StoreError};
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub postgres: &'static str,
+    pub sqlite: &'static str,
+}
+
+/// Уже применённая миграция из таблицы schema_migrations.
+#[derive(Debug, Clone)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: i64,
+}
+
+/// Состояние одной миграции для `migrate status`.
+pub struct MigrationStatus {
+    pub migration: &'static Migration,
+    pub applied_at: Option<i64>,
+}
+
+/// Состояние схемы: известные миграции и применённые версии, которых нет в этой сборке
+/// (база обновлена более новой версией программы).
+pub struct SchemaStatus {
+    pub migrations: Vec<MigrationStatus>,
+    pub unknown: Vec<AppliedMigration>,
+}
+
+// Новые миграции добавляются только в конец, уже выпущенные не редактируются.
+// Первые две повторяют прежние CREATE TABLE IF NOT EXISTS, поэтому безопасны
+// для баз, созданных до появления миграций.
+pub static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_users",
+        postgres: "CREATE TABLE IF NOT EXISTS users (
+                id SERIAL PRIMARY KEY,
+                username TEXT NOT NULL UNIQUE,
+                points BIGINT NOT NULL
+            );",
+        sqlite: "CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY,
+                username TEXT NOT NULL UNIQUE,
+                points INTEGER NOT NULL
+            );",
+    },
+    Migration {
+        version: 2,
+        name: "create_point_transactions",
+        postgres: "CREATE TABLE IF NOT EXISTS point_transactions (
+                id BIGSERIAL PRIMARY KEY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                node_id TEXT,
+                interval_start BIGINT,
+                interval_end BIGINT,
+                bytes BIGINT,
+                threshold BIGINT,
+                points BIGINT NOT NULL,
+                reason TEXT NOT NULL,
+                created_at BIGINT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS point_transactions_user_idx
+                ON point_transactions (user_id, id);
+            INSERT INTO point_transactions (user_id, points, reason, created_at)
+            SELECT id, points, 'opening_balance', EXTRACT(EPOCH FROM now())::BIGINT FROM users u
+            WHERE points <> 0
+              AND NOT EXISTS (SELECT 1 FROM point_transactions t WHERE t.user_id = u.id);",
+        sqlite: "CREATE TABLE IF NOT EXISTS point_transactions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                node_id TEXT,
+                interval_start INTEGER,
+                interval_end INTEGER,
+                bytes INTEGER,
+                threshold INTEGER,
+                points INTEGER NOT NULL,
+                reason TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS point_transactions_user_idx
+                ON point_transactions (user_id, id);
+            INSERT INTO point_transactions (user_id, points, reason, created_at)
+            SELECT id, points, 'opening_balance', CAST(strftime('%s', 'now') AS INTEGER) FROM users u
+            WHERE points <> 0
+              AND NOT EXISTS (SELECT 1 FROM point_transactions t WHERE t.user_id = u.id);",
+    },
+    Migration {
+        version: 3,
+        name: "bind_users_to_nodes",
+        postgres: "ALTER TABLE users ADD COLUMN node_id TEXT;
+            ALTER TABLE users ADD COLUMN enabled BOOLEAN NOT NULL DEFAULT TRUE;
+            CREATE INDEX users_node_idx ON users (node_id);",
+        sqlite: "ALTER TABLE users ADD COLUMN node_id TEXT;
+            ALTER TABLE users ADD COLUMN enabled INTEGER NOT NULL DEFAULT 1;
+            CREATE INDEX users_node_idx ON users (node_id);",
+    },
+    Migration {
+        version: 4,
+        name: "create_nodes",
+        postgres: "CREATE TABLE nodes (
+                id TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                secret TEXT NOT NULL,
+                enrolled_at BIGINT NOT NULL,
+                last_report_end BIGINT,
+                last_seen_at BIGINT
+            );",
+        sqlite: "CREATE TABLE nodes (
+                id TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                secret TEXT NOT NULL,
+                enrolled_at INTEGER NOT NULL,
+                last_report_end INTEGER,
+                last_seen_at INTEGER
+            );",
+    },
+    Migration {
+        version: 5,
+        name: "split_transaction_directions",
+        postgres: "ALTER TABLE point_transactions ADD COLUMN bytes_sent BIGINT;
+            ALTER TABLE point_transactions ADD COLUMN bytes_received BIGINT;
+            ALTER TABLE point_transactions ADD COLUMN upload_threshold BIGINT;
+            ALTER TABLE point_transactions ADD COLUMN download_threshold BIGINT;
+            ALTER TABLE point_transactions ADD COLUMN upload_points BIGINT;
+            ALTER TABLE point_transactions ADD COLUMN download_points BIGINT;",
+        sqlite: "ALTER TABLE point_transactions ADD COLUMN bytes_sent INTEGER;
+            ALTER TABLE point_transactions ADD COLUMN bytes_received INTEGER;
+            ALTER TABLE point_transactions ADD COLUMN upload_threshold INTEGER;
+            ALTER TABLE point_transactions ADD COLUMN download_threshold INTEGER;
+            ALTER TABLE point_transactions ADD COLUMN upload_points INTEGER;
+            ALTER TABLE point_transactions ADD COLUMN download_points INTEGER;",
+    },
+    Migration {
+        version: 6,
+        name: "record_idle_capacity",
+        postgres: "ALTER TABLE point_transactions ADD COLUMN idle_bytes_sent BIGINT;
+            ALTER TABLE point_transactions ADD COLUMN idle_bytes_received BIGINT;",
+        sqlite: "ALTER TABLE point_transactions ADD COLUMN idle_bytes_sent INTEGER;
+            ALTER TABLE point_transactions ADD COLUMN idle_bytes_received INTEGER;",
+    },
+    Migration {
+        version: 7,
+        name: "create_config_audit",
+        postgres: "CREATE TABLE config_audit (
+                id BIGSERIAL PRIMARY KEY,
+                changed_at BIGINT NOT NULL,
+                source TEXT NOT NULL,
+                node_id TEXT NOT NULL,
+                previous TEXT NOT NULL,
+                current TEXT NOT NULL
+            );",
+        sqlite: "CREATE TABLE config_audit (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                changed_at INTEGER NOT NULL,
+                source TEXT NOT NULL,
+                node_id TEXT NOT NULL,
+                previous TEXT NOT NULL,
+                current TEXT NOT NULL
+            );",
+    },
+    Migration {
+        version: 8,
+        name: "add_transaction_note",
+        postgres: "ALTER TABLE point_transactions ADD COLUMN note TEXT;",
+        sqlite: "ALTER TABLE point_transactions ADD COLUMN note TEXT;",
+    },
+    Migration {
+        version: 9,
+        name: "add_user_credentials",
+        postgres: "ALTER TABLE users ADD COLUMN password_hash TEXT;
+            ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+            CREATE TABLE sessions (
+                token_hash TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                created_at BIGINT NOT NULL,
+                expires_at BIGINT NOT NULL
+            );
+            CREATE INDEX sessions_user_idx ON sessions (user_id);",
+        sqlite: "ALTER TABLE users ADD COLUMN password_hash TEXT;
+            ALTER TABLE users ADD COLUMN is_admin INTEGER NOT NULL DEFAULT 0;
+            CREATE TABLE sessions (
+                token_hash TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                created_at INTEGER NOT NULL,
+                expires_at INTEGER NOT NULL
+            );
+            CREATE INDEX sessions_user_idx ON sessions (user_id);",
+    },
+    Migration {
+        version: 10,
+        name: "create_api_keys",
+        postgres: "CREATE TABLE api_keys (
+                id BIGSERIAL PRIMARY KEY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                node_id TEXT REFERENCES nodes(id) ON DELETE CASCADE,
+                name TEXT NOT NULL,
+                prefix TEXT NOT NULL,
+                key_hash TEXT NOT NULL UNIQUE,
+                scopes TEXT NOT NULL,
+                created_at BIGINT NOT NULL,
+                last_used_at BIGINT,
+                revoked_at BIGINT
+            );
+            CREATE INDEX api_keys_user_idx ON api_keys (user_id);",
+        sqlite: "CREATE TABLE api_keys (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                node_id TEXT REFERENCES nodes(id) ON DELETE CASCADE,
+                name TEXT NOT NULL,
+                prefix TEXT NOT NULL,
+                key_hash TEXT NOT NULL UNIQUE,
+                scopes TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                last_used_at INTEGER,
+                revoked_at INTEGER
+            );
+            CREATE INDEX api_keys_user_idx ON api_keys (user_id);",
+    },
+    Migration {
+        version: 11,
+        name: "create_traffic_history",
+        postgres: "CREATE TABLE traffic_samples (
+                id BIGSERIAL PRIMARY KEY,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                node_id TEXT NOT NULL,
+                interface TEXT NOT NULL,
+                interval_start BIGINT NOT NULL,
+                interval_end BIGINT NOT NULL,
+                bytes_sent BIGINT NOT NULL,
+                bytes_received BIGINT NOT NULL
+            );
+            CREATE INDEX traffic_samples_user_idx ON traffic_samples (user_id, interval_start);
+            CREATE INDEX traffic_samples_end_idx ON traffic_samples (interval_end);
+            CREATE TABLE traffic_rollups (
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                node_id TEXT NOT NULL,
+                interface TEXT NOT NULL,
+                bucket_secs BIGINT NOT NULL,
+                bucket_start BIGINT NOT NULL,
+                bytes_sent BIGINT NOT NULL,
+                bytes_received BIGINT NOT NULL,
+                samples BIGINT NOT NULL,
+                PRIMARY KEY (user_id, bucket_secs, bucket_start, node_id, interface)
+            );",
+        sqlite: "CREATE TABLE traffic_samples (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                node_id TEXT NOT NULL,
+                interface TEXT NOT NULL,
+                interval_start INTEGER NOT NULL,
+                interval_end INTEGER NOT NULL,
+                bytes_sent INTEGER NOT NULL,
+                bytes_received INTEGER NOT NULL
+            );
+            CREATE INDEX traffic_samples_user_idx ON traffic_samples (user_id, interval_start);
+            CREATE INDEX traffic_samples_end_idx ON traffic_samples (interval_end);
+            CREATE TABLE traffic_rollups (
+                user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                node_id TEXT NOT NULL,
+                interface TEXT NOT NULL,
+                bucket_secs INTEGER NOT NULL,
+                bucket_start INTEGER NOT NULL,
+                bytes_sent INTEGER NOT NULL,
+                bytes_received INTEGER NOT NULL,
+                samples INTEGER NOT NULL,
+                PRIMARY KEY (user_id, bucket_secs, bucket_start, node_id, interface)
+            );",
+    },
+];
+
+/// Возвращает все известные миграции с отметкой о применении.
+pub async fn status(store: &dyn PointsStore) -> Result<SchemaStatus, StoreError> {
+    let applied = store.applied_migrations().await?;
+    let migrations = MIGRATIONS
+        .iter()
+        .map(|migration| MigrationStatus {
+            migration,
+            applied_at: applied
+                .iter()
+                .find(|a| a.version == migration.version)
+                .map(|a| a.applied_at),
+        })
+        .collect();
+    let unknown = applied
+        .into_iter()
+        .filter(|a| MIGRATIONS.iter().all(|m| m.version != a.version))
+        .collect();
+    Ok(SchemaStatus { migrations, unknown })
+}
+
+/// Применяет по порядку все ещё не применённые миграции и возвращает те, что
+/// применил этот процесс. С `dry_run` только возвращает список, ничего не меняя в базе.
+pub async fn run(
+    store: &dyn PointsStore,
+    dry_run: bool,
+) -> Result<Vec<&'static Migration>, StoreError> {
+    let pending: Vec<&'static Migration> = status(store)
+        .await?
+        .migrations
+        .into_iter()
+        .filter(|s| s.applied_at.is_none())
+        .map(|s| s.migration)
+        .collect();
+
+    if dry_run {
+        return Ok(pending);
+    }
+    let mut applied = Vec::new();
+    for migration in pending {
+        if store.apply_migration(migration).await? {
+            applied.push(migration);
+        }
+    }
+    Ok(applied)
+}
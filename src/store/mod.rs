@@ -0,0 +1,535 @@
+// Хранилище поинтов: общий трейт и выбор бэкенда по DATABASE_URL
+pub mod migrations;
+mod postgres;
+mod sqlite;
+#[cfg(test)]
+mod tests;
+
+use async_trait::async_trait;
+use migrations::{AppliedMigration, Migration};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub use self::postgres::PostgresStore;
+pub use self::sqlite::SqliteStore;
+
+// Причины начислений в журнале point_transactions
+pub const REASON_TRAFFIC: &str = "traffic";
+pub const REASON_OPENING_BALANCE: &str = "opening_balance";
+pub const REASON_ADJUSTMENT: &str = "adjustment";
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("postgres error: {0}")]
+    Postgres(#[from] tokio_postgres::Error),
+    #[error("postgres pool error: {0}")]
+    Pool(#[from] deadpool_postgres::PoolError),
+    #[error("sqlite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("sqlite worker failed: {0}")]
+    Worker(#[from] tokio::task::JoinError),
+    #[error("user '{0}' not found")]
+    UserNotFound(String),
+    #[error("report overlaps an already accepted interval")]
+    ReportOverlap,
+    #[error("unsupported database url '{0}', expected postgres:// or sqlite://")]
+    UnsupportedUrl(String),
+}
+
+impl StoreError {
+    /// База недоступна (нет соединения, занята, не открывается) — запрос стоит повторить позже.
+    pub fn is_unavailable(&self) -> bool {
+        use rusqlite::ErrorCode;
+        match self {
+            StoreError::Postgres(e) => postgres_unavailable(e),
+            // Пул не смог открыть соединение — смотрим на причину; таймаут ожидания
+            // свободного соединения и закрытый пул тоже означают, что база сейчас недоступна
+            StoreError::Pool(deadpool_postgres::PoolError::Backend(e)) => postgres_unavailable(e),
+            StoreError::Pool(_) => true,
+            StoreError::Sqlite(e) => matches!(
+                e.sqlite_error_code(),
+                Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked | ErrorCode::CannotOpen | ErrorCode::SystemIoFailure)
+            ),
+            StoreError::Worker(_)
+            | StoreError::UserNotFound(_)
+            | StoreError::ReportOverlap
+            | StoreError::UnsupportedUrl(_) => false,
+        }
+    }
+}
+
+// У ошибок связи с сервером нет SQLSTATE, их причина — ошибка ввода-вывода;
+// класс 08 — ошибки соединения, 53 — нехватка ресурсов, 57P — остановка сервера
+fn postgres_unavailable(e: &tokio_postgres::Error) -> bool {
+    match e.code() {
+        None => e.is_closed() || std::error::Error::source(e).is_some_and(|source| source.is::<std::io::Error>()),
+        Some(state) => ["08", "53", "57P"].iter().any(|class| state.code().starts_with(class)),
+    }
+}
+
+/// Параметры пула соединений PostgreSQL. SQLite работает через одно соединение и их не использует.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolOptions {
+    /// Наибольшее число открытых соединений
+    pub max_size: usize,
+    /// Сколько ждать открытия соединения или свободного соединения из пула
+    pub connect_timeout: Duration,
+    /// Предел выполнения одного запроса на сервере; `None` — без предела
+    pub statement_timeout: Option<Duration>,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        PoolOptions {
+            max_size: 16,
+            connect_timeout: Duration::from_secs(5),
+            statement_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+/// Подробности начисления за трафик по направлениям.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TrafficDetails {
+    pub bytes_sent: i64,
+    pub bytes_received: i64,
+    pub upload_threshold: i64,
+    pub download_threshold: i64,
+    pub upload_points: i64,
+    pub download_points: i64,
+    /// Неиспользованная ёмкость линков; заполнена, если оплачивалась она, а не трафик
+    pub idle_bytes_sent: Option<i64>,
+    pub idle_bytes_received: Option<i64>,
+}
+
+/// Новая запись журнала начислений. Интервал и трафик пустые для начислений не за трафик.
+#[derive(Debug, Clone)]
+pub struct NewTransaction {
+    pub username: String,
+    pub node_id: Option<String>,
+    pub interval_start: Option<i64>,
+    pub interval_end: Option<i64>,
+    pub traffic: Option<TrafficDetails>,
+    pub points: i64,
+    pub reason: String,
+    /// Пояснение оператора к ручной корректировке
+    pub note: Option<String>,
+}
+
+/// Запись журнала начислений в том виде, в каком она хранится в базе.
+#[derive(Debug, Clone, Serialize)]
+pub struct Transaction {
+    pub id: i64,
+    pub node_id: Option<String>,
+    pub interval_start: Option<i64>,
+    pub interval_end: Option<i64>,
+    /// Трафик в обоих направлениях вместе
+    pub bytes: Option<i64>,
+    /// Общий порог; заполнен только у записей до разделения направлений
+    pub threshold: Option<i64>,
+    pub traffic: Option<TrafficDetails>,
+    pub points: i64,
+    pub reason: String,
+    pub note: Option<String>,
+    pub created_at: i64,
+}
+
+/// Аккаунт пользователя и нода, с которой он фармит.
+/// Аккаунт без ноды ничего не зарабатывает, пока к нему не привяжут ноду.
+#[derive(Debug, Clone, Serialize)]
+pub struct Account {
+    pub username: String,
+    pub points: i64,
+    pub node_id: Option<String>,
+    pub enabled: bool,
+    pub is_admin: bool,
+}
+
+/// Итог регистрации ноды.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enrollment {
+    Created,
+    /// Нода с таким id уже зарегистрирована
+    NodeTaken,
+    /// Аккаунт уже привязан к ноде; перепривязать его может только оператор
+    AccountBound,
+}
+
+/// Итог привязки аккаунта к ноде оператором.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Bound,
+    /// К ноде уже привязан другой аккаунт: один трафик не оплачивается дважды
+    NodeTaken,
+    /// За аккаунтом зарегистрирована нода-агент, и её отчёты зачисляются ему;
+    /// перепривязка разошлась бы с таблицей нод
+    OwnsNode,
+}
+
+/// Зарегистрированная нода-агент. Секрет хранится открыто: сервер проверяет им
+/// HMAC-подписи отчётов.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: String,
+    pub username: String,
+    pub user_enabled: bool,
+    pub secret: String,
+    /// Время регистрации: раньше него отчёты ноды начинаться не могут
+    pub enrolled_at: i64,
+}
+
+/// Учётные данные для входа. У аккаунтов, созданных из CLI, пароля может не быть.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password_hash: Option<String>,
+    pub is_admin: bool,
+    pub enabled: bool,
+}
+
+/// Пользователь, которому принадлежит действующая сессия.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionUser {
+    pub username: String,
+    pub is_admin: bool,
+}
+
+/// Новый API-ключ. Сам ключ не хранится — только его SHA-256 и префикс для узнавания в списке.
+#[derive(Debug, Clone)]
+pub struct NewApiKey {
+    pub username: String,
+    /// Ключ ноды действует только для неё
+    pub node_id: Option<String>,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    /// Права через пробел, например `stats:read usage:submit`
+    pub scopes: String,
+}
+
+/// API-ключ в том виде, в каком он хранится в базе.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: i64,
+    pub username: String,
+    pub node_id: Option<String>,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+/// Действующий API-ключ вместе с правами его владельца.
+#[derive(Debug, Clone)]
+pub struct ApiKeyOwner {
+    pub key: ApiKey,
+    pub is_admin: bool,
+    pub enabled: bool,
+}
+
+/// Запись журнала изменений политики начисления. Политика до и после хранится в JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigChange {
+    pub id: i64,
+    pub changed_at: i64,
+    /// Откуда пришло изменение: admin API или перечитывание файла по SIGHUP
+    pub source: String,
+    pub node_id: String,
+    pub previous: serde_json::Value,
+    pub current: serde_json::Value,
+}
+
+/// Начала периодов, за которые считается заработок (unix-время).
+#[derive(Debug, Clone, Copy)]
+pub struct EarningPeriods {
+    /// Полночь UTC текущих суток
+    pub today: i64,
+    pub last_7_days: i64,
+    pub last_30_days: i64,
+}
+
+impl EarningPeriods {
+    pub fn ending_at(now: i64) -> Self {
+        const DAY: i64 = 24 * 3600;
+        EarningPeriods { today: now - now.rem_euclid(DAY), last_7_days: now - 7 * DAY, last_30_days: now - 30 * DAY }
+    }
+}
+
+/// Последнее начисление за трафик.
+#[derive(Debug, Clone, Serialize)]
+pub struct LastCredit {
+    pub node_id: Option<String>,
+    pub interval_start: Option<i64>,
+    pub interval_end: Option<i64>,
+    pub points: i64,
+    pub created_at: i64,
+}
+
+/// Заработок по журналу: только начисления за трафик, без начального баланса
+/// и ручных корректировок.
+#[derive(Debug, Clone, Serialize)]
+pub struct Earnings {
+    pub today: i64,
+    pub last_7_days: i64,
+    pub last_30_days: i64,
+    pub all_time: i64,
+    pub last_credit: Option<LastCredit>,
+}
+
+/// Заработок за трафик за одни сутки.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DailyEarning {
+    /// Начало суток (unix-время)
+    pub day_start: i64,
+    pub points: i64,
+}
+
+/// Нода, с которой фармит пользователь, и когда она последний раз присылала отчёт.
+/// У аккаунтов на ноде сервера `last_seen_at` пустой: их видит только локальный монитор.
+#[derive(Debug, Clone)]
+pub struct NodeStatus {
+    pub node_id: Option<String>,
+    pub enabled: bool,
+    pub last_seen_at: Option<i64>,
+}
+
+/// Размеры корзин, в которые сворачивается история трафика: час и сутки.
+pub const ROLLUP_BUCKETS: [i64; 2] = [3600, 24 * 3600];
+
+/// Трафик одного интерфейса ноды за интервал измерения.
+#[derive(Debug, Clone)]
+pub struct TrafficSample {
+    pub username: String,
+    pub node_id: String,
+    pub interface: String,
+    pub interval_start: i64,
+    pub interval_end: i64,
+    pub bytes_sent: i64,
+    pub bytes_received: i64,
+}
+
+/// Выборка истории трафика пользователя за [from, to).
+#[derive(Debug, Clone)]
+pub struct TrafficQuery {
+    pub username: String,
+    pub from: i64,
+    pub to: i64,
+    /// Размер корзины из `ROLLUP_BUCKETS`; `None` — исходные интервалы
+    pub bucket_secs: Option<i64>,
+    pub node_id: Option<String>,
+    pub interface: Option<String>,
+}
+
+/// Точка ряда: трафик, суммированный по нодам и интерфейсам, за [start, end).
+#[derive(Debug, Clone, Serialize)]
+pub struct TrafficPoint {
+    pub start: i64,
+    pub end: i64,
+    pub bytes_sent: i64,
+    pub bytes_received: i64,
+}
+
+/// Сколько записей удалило очередное применение сроков хранения.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrunedTraffic {
+    pub samples: u64,
+    pub rollups: u64,
+}
+
+/// Баланс пользователя и сумма его журнала; при расхождении они не равны.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Reconciliation {
+    pub balance: i64,
+    pub ledger_balance: i64,
+}
+
+impl Reconciliation {
+    pub fn is_consistent(&self) -> bool {
+        self.balance == self.ledger_balance
+    }
+}
+
+/// Операции с балансами пользователей, которые нужны серверу и монитору.
+#[async_trait]
+pub trait PointsStore: Send + Sync {
+    /// Проверяет, что база отвечает на запросы.
+    async fn ping(&self) -> Result<(), StoreError>;
+
+    /// Возвращает применённые миграции; пустой список, если таблицы версий ещё нет.
+    async fn applied_migrations(&self) -> Result<Vec<AppliedMigration>, StoreError>;
+
+    /// Применяет миграцию и записывает её версию в одной транзакции. Процессы,
+    /// одновременно мигрирующие одну базу, делают это по очереди; возвращает
+    /// `false`, если миграцию уже успел применить другой процесс.
+    async fn apply_migration(&self, migration: &Migration) -> Result<bool, StoreError>;
+
+    /// Создаёт пользователя, если его ещё нет, и возвращает `false`, если он уже был.
+    /// Ненулевой стартовый баланс записывается в журнал как `opening_balance`.
+    async fn add_user(&self, username: &str, points: i64) -> Result<bool, StoreError>;
+
+    /// Возвращает все аккаунты в порядке создания.
+    async fn list_users(&self) -> Result<Vec<Account>, StoreError>;
+
+    /// Имена включённых аккаунтов, явно привязанных к ноде `node_id`, в порядке создания.
+    /// Аккаунты без ноды сюда не попадают: им не за что начислять.
+    async fn monitored_users(&self, node_id: &str) -> Result<Vec<String>, StoreError>;
+
+    /// Включает или отключает начисления аккаунту.
+    async fn set_user_enabled(&self, username: &str, enabled: bool) -> Result<(), StoreError>;
+
+    /// Привязывает аккаунт к ноде или отвязывает при `None`. Не трогает аккаунты
+    /// с зарегистрированной нодой и не привязывает второй аккаунт к занятой ноде.
+    async fn set_user_node(&self, username: &str, node_id: Option<&str>) -> Result<Binding, StoreError>;
+
+    /// Удаляет аккаунт вместе с его журналом.
+    async fn delete_user(&self, username: &str) -> Result<(), StoreError>;
+
+    /// Регистрирует ноду за пользователем и привязывает к ней его аккаунт.
+    /// Аккаунт, уже привязанный к какой-либо ноде, не трогает.
+    async fn create_node(&self, node_id: &str, username: &str, secret: &str) -> Result<Enrollment, StoreError>;
+
+    /// Возвращает зарегистрированную ноду.
+    async fn get_node(&self, node_id: &str) -> Result<Option<Node>, StoreError>;
+
+    /// Принимает отчёт ноды за интервал одной транзакцией: сдвигает её отметку последнего
+    /// отчёта и проводит начисление `credit`, если оно есть. Сбой начисления откатывает и
+    /// отметку, так что агент может повторить тот же отчёт. Возвращает баланс после начисления.
+    /// Интервал, начинающийся раньше конца уже принятого (повтор или перекрытие),
+    /// ничего не меняет и возвращает `ReportOverlap`.
+    async fn accept_node_report(
+        &self,
+        node_id: &str,
+        interval_start: i64,
+        interval_end: i64,
+        credit: Option<&NewTransaction>,
+    ) -> Result<Option<i64>, StoreError>;
+
+    /// Возвращает текущий баланс пользователя.
+    async fn get_user_points(&self, username: &str) -> Result<i64, StoreError>;
+
+    /// Атомарно прибавляет `points` к балансу, пишет запись в журнал и возвращает новый баланс.
+    /// Для несуществующего пользователя ничего не пишет и возвращает `UserNotFound`.
+    async fn credit_points(&self, entry: &NewTransaction) -> Result<i64, StoreError>;
+
+    /// Возвращает записи журнала пользователя от новых к старым.
+    /// `before` — id записи, с которой продолжить постраничный вывод.
+    async fn list_transactions(
+        &self,
+        username: &str,
+        limit: i64,
+        before: Option<i64>,
+    ) -> Result<Vec<Transaction>, StoreError>;
+
+    /// Сверяет сохранённый баланс с суммой журнала.
+    async fn reconcile(&self, username: &str) -> Result<Reconciliation, StoreError>;
+
+    /// Считает заработок пользователя за периоды по журналу начислений.
+    async fn earnings(&self, username: &str, periods: &EarningPeriods) -> Result<Earnings, StoreError>;
+
+    /// Заработок за трафик по суткам, начиная с `since`. Сутки начинаются в полночь
+    /// часового пояса со сдвигом `utc_offset` секунд от UTC; дни без начислений пропускаются.
+    async fn daily_earnings(
+        &self,
+        username: &str,
+        since: i64,
+        utc_offset: i64,
+    ) -> Result<Vec<DailyEarning>, StoreError>;
+
+    /// Возвращает ноду пользователя и время её последнего отчёта.
+    async fn node_status(&self, username: &str) -> Result<NodeStatus, StoreError>;
+
+    /// Число нод включённых пользователей, отчитавшихся не раньше `since`.
+    async fn count_active_nodes(&self, since: i64) -> Result<i64, StoreError>;
+
+    /// Сохраняет интервалы трафика и добавляет их в часовые и суточные корзины.
+    /// Интервал попадает в корзину, в которую приходится его начало.
+    async fn record_traffic(&self, samples: &[TrafficSample]) -> Result<(), StoreError>;
+
+    /// Возвращает ряд трафика пользователя, упорядоченный по времени.
+    async fn traffic_series(&self, query: &TrafficQuery) -> Result<Vec<TrafficPoint>, StoreError>;
+
+    /// Удаляет интервалы, закончившиеся до `samples_before`, и корзины, начавшиеся
+    /// до срока для их размера (`(bucket_secs, before)`).
+    async fn prune_traffic(
+        &self,
+        samples_before: Option<i64>,
+        rollups_before: &[(i64, i64)],
+    ) -> Result<PrunedTraffic, StoreError>;
+
+    /// Создаёт аккаунт с паролем, без ноды: начислений нет, пока ноду не зарегистрируют
+    /// или не привяжет оператор. Возвращает `false`, если имя уже занято.
+    async fn create_account(&self, username: &str, password_hash: &str) -> Result<bool, StoreError>;
+
+    /// Меняет пароль и завершает все сессии пользователя.
+    async fn set_password(&self, username: &str, password_hash: &str) -> Result<(), StoreError>;
+
+    /// Выдаёт или отзывает права администратора.
+    async fn set_user_admin(&self, username: &str, is_admin: bool) -> Result<(), StoreError>;
+
+    /// Возвращает учётные данные пользователя для проверки пароля.
+    async fn get_credentials(&self, username: &str) -> Result<Option<Credentials>, StoreError>;
+
+    /// Сохраняет сессию по хешу её токена; заодно удаляет истёкшие сессии.
+    async fn create_session(&self, username: &str, token_hash: &str, expires_at: i64) -> Result<(), StoreError>;
+
+    /// Возвращает владельца действующей (не истёкшей) сессии.
+    async fn get_session(&self, token_hash: &str) -> Result<Option<SessionUser>, StoreError>;
+
+    /// Завершает сессию.
+    async fn delete_session(&self, token_hash: &str) -> Result<(), StoreError>;
+
+    /// Сохраняет API-ключ и возвращает его id.
+    async fn create_api_key(&self, key: &NewApiKey) -> Result<i64, StoreError>;
+
+    /// Ключи пользователя (или всех пользователей), от новых к старым, включая отозванные.
+    async fn list_api_keys(&self, username: Option<&str>) -> Result<Vec<ApiKey>, StoreError>;
+
+    /// Отзывает ключ. Если задан `username`, отзывает только ключ этого пользователя.
+    /// Возвращает `false`, если действующего ключа не нашлось.
+    async fn revoke_api_key(&self, id: i64, username: Option<&str>) -> Result<bool, StoreError>;
+
+    /// Находит неотозванный ключ по хешу и отмечает время его использования.
+    async fn authenticate_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyOwner>, StoreError>;
+
+    /// Записывает изменение политики начисления в журнал аудита.
+    async fn record_config_change(
+        &self,
+        source: &str,
+        node_id: &str,
+        previous: &serde_json::Value,
+        current: &serde_json::Value,
+    ) -> Result<(), StoreError>;
+
+    /// Возвращает последние изменения политики от новых к старым.
+    async fn list_config_changes(&self, limit: i64) -> Result<Vec<ConfigChange>, StoreError>;
+}
+
+/// Открывает хранилище по схеме URL: `postgres://`/`postgresql://` или `sqlite://<путь>`.
+/// Схему не трогает — для этого есть `migrations::run`.
+pub async fn connect(database_url: &str, options: &PoolOptions) -> Result<Arc<dyn PointsStore>, StoreError> {
+    if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+        Ok(Arc::new(PostgresStore::connect(database_url, options).await?))
+    } else if let Some(path) = database_url.strip_prefix("sqlite://") {
+        Ok(Arc::new(SqliteStore::open(path).await?))
+    } else if database_url == "sqlite::memory:" {
+        Ok(Arc::new(SqliteStore::open(":memory:").await?))
+    } else {
+        Err(StoreError::UnsupportedUrl(database_url.to_string()))
+    }
+}
+
+// Журнал аудита хранит JSON текстом; повреждённую запись показываем как строку, а не теряем
+fn parse_json(text: String) -> serde_json::Value {
+    serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text))
+}
+
+/// Текущее время в секундах Unix — в таком виде в базе хранятся все метки времени.
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
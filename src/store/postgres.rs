@@ -0,0 +1,881 @@
+// Бэкенд PostgreSQL
+use super::migrations::{AppliedMigration, Migration};
+use super::{
+    parse_json, unix_now, Account, ApiKey, ApiKeyOwner, Binding, ConfigChange, Credentials, DailyEarning,
+    EarningPeriods, Earnings, Enrollment, LastCredit, NewApiKey, NewTransaction, Node, NodeStatus, PointsStore,
+    PoolOptions, PrunedTraffic, Reconciliation, SessionUser, StoreError, TrafficDetails, TrafficPoint, TrafficQuery,
+    TrafficSample, Transaction, REASON_OPENING_BALANCE, REASON_TRAFFIC, ROLLUP_BUCKETS,
+};
+use async_trait::async_trait;
+use deadpool_postgres::{GenericClient, Manager, ManagerConfig, Object, Pool, RecyclingMethod, Runtime};
+use std::time::Duration;
+use tokio_postgres::{NoTls, Row};
+
+/// Сколько раз пробовать первое соединение, пока сервер базы поднимается.
+const CONNECT_ATTEMPTS: u32 = 7;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(15);
+/// Ключ advisory-блокировки, под которой процессы применяют миграции по очереди.
+const MIGRATION_LOCK: i64 = 0x6e66_6d69_6772;
+
+/// Хранилище на пуле соединений. Разорванное соединение пул отбрасывает при выдаче
+/// (перед выдачей соединение проверяется запросом) и открывает новое, так что после
+/// перезапуска базы запросы восстанавливаются сами.
+pub struct PostgresStore {
+    pool: Pool,
+}
+
+impl PostgresStore {
+    pub async fn connect(database_url: &str, options: &PoolOptions) -> Result<Self, StoreError> {
+        let mut config: tokio_postgres::Config = database_url.parse()?;
+        config.connect_timeout(options.connect_timeout);
+        if let Some(timeout) = options.statement_timeout {
+            // Параметры сессии из URL сохраняем, таймаут добавляем к ним
+            let statement_timeout = format!("-c statement_timeout={}", timeout.as_millis());
+            let session_options = match config.get_options() {
+                Some(existing) => format!("{} {}", existing, statement_timeout),
+                None => statement_timeout,
+            };
+            config.options(&session_options);
+        }
+        let manager =
+            Manager::from_config(config, NoTls, ManagerConfig { recycling_method: RecyclingMethod::Verified });
+        let pool = Pool::builder(manager)
+            .max_size(options.max_size)
+            .wait_timeout(Some(options.connect_timeout))
+            .create_timeout(Some(options.connect_timeout))
+            .recycle_timeout(Some(options.connect_timeout))
+            .runtime(Runtime::Tokio1)
+            .build()
+            .expect("pool runtime is set");
+        let store = PostgresStore { pool };
+
+        // База может подниматься одновременно с нами (docker compose): ждём её с нарастающей паузой
+        let mut delay = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match store.pool.get().await {
+                Ok(_) => return Ok(store),
+                Err(e) if attempt < CONNECT_ATTEMPTS => {
+                    tracing::warn!(attempt, error = %e, retry_in = ?delay, "Database is not reachable yet");
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_BACKOFF);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    async fn client(&self) -> Result<Object, StoreError> {
+        Ok(self.pool.get().await?)
+    }
+
+    /// Удаляет все таблицы — тесты начинают с пустой схемы.
+    #[cfg(test)]
+    pub(super) async fn drop_schema(&self) -> Result<(), StoreError> {
+        self.client().await?.batch_execute("DROP SCHEMA public CASCADE; CREATE SCHEMA public;").await?;
+        Ok(())
+    }
+}
+
+// Начисление на соединении или внутри транзакции вызывающего
+async fn credit_in(client: &impl GenericClient, entry: &NewTransaction) -> Result<i64, StoreError> {
+    // Один оператор с CTE выполняется атомарно: баланс и журнал меняются вместе
+    let traffic = entry.traffic.as_ref();
+    let row = client
+        .query_opt(
+            "WITH credited AS (
+                UPDATE users SET points = points + $2 WHERE username = $1
+                RETURNING id, points
+             ), entry AS (
+                INSERT INTO point_transactions
+                    (user_id, node_id, interval_start, interval_end, bytes,
+                     bytes_sent, bytes_received, upload_threshold, download_threshold,
+                     upload_points, download_points, idle_bytes_sent, idle_bytes_received,
+                     points, reason, note, created_at)
+                SELECT id, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $2, $15, $16, $17
+                FROM credited
+             )
+             SELECT points FROM credited",
+            &[
+                &entry.username,
+                &entry.points,
+                &entry.node_id,
+                &entry.interval_start,
+                &entry.interval_end,
+                &traffic.map(|t| t.bytes_sent + t.bytes_received),
+                &traffic.map(|t| t.bytes_sent),
+                &traffic.map(|t| t.bytes_received),
+                &traffic.map(|t| t.upload_threshold),
+                &traffic.map(|t| t.download_threshold),
+                &traffic.map(|t| t.upload_points),
+                &traffic.map(|t| t.download_points),
+                &traffic.and_then(|t| t.idle_bytes_sent),
+                &traffic.and_then(|t| t.idle_bytes_received),
+                &entry.reason,
+                &entry.note,
+                &unix_now(),
+            ],
+        )
+        .await?;
+    match row {
+        Some(row) => Ok(row.get(0)),
+        None => Err(StoreError::UserNotFound(entry.username.clone())),
+    }
+}
+
+fn transaction_from_row(row: &Row) -> Transaction {
+    Transaction {
+        id: row.get("id"),
+        node_id: row.get("node_id"),
+        interval_start: row.get("interval_start"),
+        interval_end: row.get("interval_end"),
+        bytes: row.get("bytes"),
+        threshold: row.get("threshold"),
+        traffic: row.get::<_, Option<i64>>("bytes_sent").map(|bytes_sent| TrafficDetails {
+            bytes_sent,
+            bytes_received: row.get::<_, Option<i64>>("bytes_received").unwrap_or(0),
+            upload_threshold: row.get::<_, Option<i64>>("upload_threshold").unwrap_or(0),
+            download_threshold: row.get::<_, Option<i64>>("download_threshold").unwrap_or(0),
+            upload_points: row.get::<_, Option<i64>>("upload_points").unwrap_or(0),
+            download_points: row.get::<_, Option<i64>>("download_points").unwrap_or(0),
+            idle_bytes_sent: row.get("idle_bytes_sent"),
+            idle_bytes_received: row.get("idle_bytes_received"),
+        }),
+        points: row.get("points"),
+        reason: row.get("reason"),
+        note: row.get("note"),
+        created_at: row.get("created_at"),
+    }
+}
+
+fn api_key_from_row(row: &Row) -> ApiKey {
+    ApiKey {
+        id: row.get(0),
+        username: row.get(1),
+        node_id: row.get(2),
+        name: row.get(3),
+        prefix: row.get(4),
+        scopes: row.get::<_, String>(5).split_whitespace().map(str::to_string).collect(),
+        created_at: row.get(6),
+        last_used_at: row.get(7),
+        revoked_at: row.get(8),
+    }
+}
+
+#[async_trait]
+impl PointsStore for PostgresStore {
+    async fn ping(&self) -> Result<(), StoreError> {
+        self.client().await?.simple_query("SELECT 1").await?;
+        Ok(())
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<AppliedMigration>, StoreError> {
+        let client = self.client().await?;
+        let exists: bool = client
+            .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[])
+            .await?
+            .get(0);
+        if !exists {
+            return Ok(Vec::new());
+        }
+        let rows = client
+            .query("SELECT version, name, applied_at FROM schema_migrations ORDER BY version", &[])
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| AppliedMigration {
+                version: row.get(0),
+                name: row.get(1),
+                applied_at: row.get(2),
+            })
+            .collect())
+    }
+
+    async fn apply_migration(&self, migration: &Migration) -> Result<bool, StoreError> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        // Блокировка держится до конца транзакции: другой процесс ждёт её здесь,
+        // а получив, видит уже применённую версию
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK]).await?;
+        tx.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                name TEXT NOT NULL,
+                applied_at BIGINT NOT NULL
+            );",
+        )
+        .await?;
+        let applied = tx
+            .query_opt("SELECT 1 FROM schema_migrations WHERE version = $1", &[&migration.version])
+            .await?
+            .is_some();
+        if !applied {
+            tx.batch_execute(migration.postgres).await?;
+            tx.execute(
+                "INSERT INTO schema_migrations (version, name, applied_at) VALUES ($1, $2, $3)",
+                &[&migration.version, &migration.name, &unix_now()],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(!applied)
+    }
+
+    async fn add_user(&self, username: &str, points: i64) -> Result<bool, StoreError> {
+        let row = self
+            .client()
+            .await?
+            .query_one(
+                "WITH created AS (
+                    INSERT INTO users (username, points) VALUES ($1, $2)
+                    ON CONFLICT (username) DO NOTHING
+                    RETURNING id, points
+                 ),
+                 opening AS (
+                    INSERT INTO point_transactions (user_id, points, reason, created_at)
+                    SELECT id, points, $3, $4 FROM created WHERE points <> 0
+                 )
+                 SELECT EXISTS (SELECT 1 FROM created)",
+                &[&username, &points, &REASON_OPENING_BALANCE, &unix_now()],
+            )
+            .await?;
+        Ok(row.get(0))
+    }
+
+    async fn list_users(&self) -> Result<Vec<Account>, StoreError> {
+        let rows = self
+            .client()
+            .await?
+            .query("SELECT username, points, node_id, enabled, is_admin FROM users ORDER BY id", &[])
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| Account {
+                username: row.get(0),
+                points: row.get(1),
+                node_id: row.get(2),
+                enabled: row.get(3),
+                is_admin: row.get(4),
+            })
+            .collect())
+    }
+
+    async fn monitored_users(&self, node_id: &str) -> Result<Vec<String>, StoreError> {
+        let rows = self
+            .client()
+            .await?
+            .query(
+                "SELECT username FROM users
+                 WHERE enabled AND node_id = $1
+                 ORDER BY id",
+                &[&node_id],
+            )
+            .await?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn set_user_enabled(&self, username: &str, enabled: bool) -> Result<(), StoreError> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let updated = tx.execute("UPDATE users SET enabled = $1 WHERE username = $2", &[&enabled, &username]).await?;
+        if updated == 0 {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        }
+        // Отключённый аккаунт выходит из всех сессий сразу
+        if !enabled {
+            tx.execute(
+                "DELETE FROM sessions WHERE user_id = (SELECT id FROM users WHERE username = $1)",
+                &[&username],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn set_user_node(&self, username: &str, node_id: Option<&str>) -> Result<Binding, StoreError> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let Some(user) = tx.query_opt("SELECT id FROM users WHERE username = $1 FOR UPDATE", &[&username]).await? else {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        };
+        let user_id: i32 = user.get(0);
+        let owned: Option<String> =
+            tx.query_opt("SELECT id FROM nodes WHERE user_id = $1", &[&user_id]).await?.map(|row| row.get(0));
+        if owned.is_some() && owned.as_deref() != node_id {
+            return Ok(Binding::OwnsNode);
+        }
+        let taken: bool = tx
+            .query_one(
+                "SELECT EXISTS (SELECT 1 FROM users WHERE node_id = $1 AND id <> $2)
+                     OR EXISTS (SELECT 1 FROM nodes WHERE id = $1 AND user_id <> $2)",
+                &[&node_id, &user_id],
+            )
+            .await?
+            .get(0);
+        if taken {
+            return Ok(Binding::NodeTaken);
+        }
+        tx.execute("UPDATE users SET node_id = $1 WHERE id = $2", &[&node_id, &user_id]).await?;
+        tx.commit().await?;
+        Ok(Binding::Bound)
+    }
+
+    async fn delete_user(&self, username: &str) -> Result<(), StoreError> {
+        let deleted = self
+            .client()
+            .await?
+            .execute("DELETE FROM users WHERE username = $1", &[&username])
+            .await?;
+        if deleted == 0 {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn create_node(&self, node_id: &str, username: &str, secret: &str) -> Result<Enrollment, StoreError> {
+        // Строка аккаунта блокируется: две одновременные регистрации не привяжут его дважды
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "WITH owner AS (
+                    SELECT id, node_id FROM users WHERE username = $2 FOR UPDATE
+                 ), created AS (
+                    INSERT INTO nodes (id, user_id, secret, enrolled_at)
+                    SELECT $1, id, $3, $4 FROM owner WHERE node_id IS NULL
+                    ON CONFLICT (id) DO NOTHING
+                    RETURNING user_id
+                 ), bound AS (
+                    UPDATE users SET node_id = $1 WHERE id IN (SELECT user_id FROM created)
+                 )
+                 SELECT owner.node_id IS NOT NULL, (SELECT COUNT(*) FROM created) FROM owner",
+                &[&node_id, &username, &secret, &unix_now()],
+            )
+            .await?;
+        let Some(row) = row else {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        };
+        let (already_bound, created): (bool, i64) = (row.get(0), row.get(1));
+        Ok(match (already_bound, created) {
+            (true, _) => Enrollment::AccountBound,
+            (false, 0) => Enrollment::NodeTaken,
+            (false, _) => Enrollment::Created,
+        })
+    }
+
+    async fn get_node(&self, node_id: &str) -> Result<Option<Node>, StoreError> {
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "SELECT n.id, u.username, u.enabled, n.secret, n.enrolled_at
+                 FROM nodes n JOIN users u ON u.id = n.user_id
+                 WHERE n.id = $1",
+                &[&node_id],
+            )
+            .await?;
+        Ok(row.map(|row| Node {
+            id: row.get(0),
+            username: row.get(1),
+            user_enabled: row.get(2),
+            secret: row.get(3),
+            enrolled_at: row.get(4),
+        }))
+    }
+
+    async fn accept_node_report(
+        &self,
+        node_id: &str,
+        interval_start: i64,
+        interval_end: i64,
+        credit: Option<&NewTransaction>,
+    ) -> Result<Option<i64>, StoreError> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let accepted = tx
+            .execute(
+                "UPDATE nodes SET last_report_end = $3, last_seen_at = $4
+                 WHERE id = $1 AND (last_report_end IS NULL OR last_report_end <= $2)",
+                &[&node_id, &interval_start, &interval_end, &unix_now()],
+            )
+            .await?
+            > 0;
+        if !accepted {
+            return Err(StoreError::ReportOverlap);
+        }
+        let balance = match credit {
+            Some(entry) => Some(credit_in(&tx, entry).await?),
+            None => None,
+        };
+        tx.commit().await?;
+        Ok(balance)
+    }
+
+    async fn get_user_points(&self, username: &str) -> Result<i64, StoreError> {
+        let row = self
+            .client()
+            .await?
+            .query_opt("SELECT points FROM users WHERE username = $1", &[&username])
+            .await?
+            .ok_or_else(|| StoreError::UserNotFound(username.to_string()))?;
+        Ok(row.get(0))
+    }
+
+    async fn credit_points(&self, entry: &NewTransaction) -> Result<i64, StoreError> {
+        credit_in(&self.client().await?, entry).await
+    }
+
+    async fn list_transactions(
+        &self,
+        username: &str,
+        limit: i64,
+        before: Option<i64>,
+    ) -> Result<Vec<Transaction>, StoreError> {
+        let rows = self
+            .client()
+            .await?
+            .query(
+                "SELECT t.id, t.node_id, t.interval_start, t.interval_end, t.bytes, t.threshold,
+                        t.bytes_sent, t.bytes_received, t.upload_threshold, t.download_threshold,
+                        t.upload_points, t.download_points, t.idle_bytes_sent, t.idle_bytes_received,
+                        t.points, t.reason, t.note, t.created_at
+                 FROM point_transactions t JOIN users u ON u.id = t.user_id
+                 WHERE u.username = $1 AND ($2::BIGINT IS NULL OR t.id < $2)
+                 ORDER BY t.id DESC
+                 LIMIT $3",
+                &[&username, &before, &limit],
+            )
+            .await?;
+        Ok(rows.iter().map(transaction_from_row).collect())
+    }
+
+    async fn reconcile(&self, username: &str) -> Result<Reconciliation, StoreError> {
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "SELECT u.points,
+                        COALESCE((SELECT SUM(points) FROM point_transactions WHERE user_id = u.id), 0)::BIGINT
+                 FROM users u WHERE u.username = $1",
+                &[&username],
+            )
+            .await?
+            .ok_or_else(|| StoreError::UserNotFound(username.to_string()))?;
+        Ok(Reconciliation { balance: row.get(0), ledger_balance: row.get(1) })
+    }
+
+    async fn earnings(&self, username: &str, periods: &EarningPeriods) -> Result<Earnings, StoreError> {
+        let totals = self
+            .client()
+            .await?
+            .query_opt(
+                "SELECT COALESCE(SUM(t.points) FILTER (WHERE t.created_at >= $2), 0)::BIGINT,
+                        COALESCE(SUM(t.points) FILTER (WHERE t.created_at >= $3), 0)::BIGINT,
+                        COALESCE(SUM(t.points) FILTER (WHERE t.created_at >= $4), 0)::BIGINT,
+                        COALESCE(SUM(t.points), 0)::BIGINT
+                 FROM users u
+                 LEFT JOIN point_transactions t ON t.user_id = u.id AND t.reason = $5
+                 WHERE u.username = $1
+                 GROUP BY u.id",
+                &[&username, &periods.today, &periods.last_7_days, &periods.last_30_days, &REASON_TRAFFIC],
+            )
+            .await?
+            .ok_or_else(|| StoreError::UserNotFound(username.to_string()))?;
+        let last_credit = self
+            .client()
+            .await?
+            .query_opt(
+                "SELECT t.node_id, t.interval_start, t.interval_end, t.points, t.created_at
+                 FROM point_transactions t JOIN users u ON u.id = t.user_id
+                 WHERE u.username = $1 AND t.reason = $2
+                 ORDER BY t.id DESC LIMIT 1",
+                &[&username, &REASON_TRAFFIC],
+            )
+            .await?;
+        Ok(Earnings {
+            today: totals.get(0),
+            last_7_days: totals.get(1),
+            last_30_days: totals.get(2),
+            all_time: totals.get(3),
+            last_credit: last_credit.map(|row| LastCredit {
+                node_id: row.get(0),
+                interval_start: row.get(1),
+                interval_end: row.get(2),
+                points: row.get(3),
+                created_at: row.get(4),
+            }),
+        })
+    }
+
+    async fn daily_earnings(
+        &self,
+        username: &str,
+        since: i64,
+        utc_offset: i64,
+    ) -> Result<Vec<DailyEarning>, StoreError> {
+        let client = self.client().await?;
+        let Some(user) = client.query_opt("SELECT id FROM users WHERE username = $1", &[&username]).await? else {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        };
+        let user_id: i32 = user.get(0);
+        let rows = client
+            .query(
+                "SELECT (created_at + $3) / 86400 * 86400 - $3 AS day_start, SUM(points)::BIGINT
+                 FROM point_transactions
+                 WHERE user_id = $1 AND reason = $4 AND created_at >= $2
+                 GROUP BY day_start
+                 ORDER BY day_start",
+                &[&user_id, &since, &utc_offset, &REASON_TRAFFIC],
+            )
+            .await?;
+        Ok(rows.iter().map(|row| DailyEarning { day_start: row.get(0), points: row.get(1) }).collect())
+    }
+
+    async fn node_status(&self, username: &str) -> Result<NodeStatus, StoreError> {
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "SELECT u.node_id, u.enabled, n.last_seen_at
+                 FROM users u LEFT JOIN nodes n ON n.id = u.node_id
+                 WHERE u.username = $1",
+                &[&username],
+            )
+            .await?
+            .ok_or_else(|| StoreError::UserNotFound(username.to_string()))?;
+        Ok(NodeStatus { node_id: row.get(0), enabled: row.get(1), last_seen_at: row.get(2) })
+    }
+
+    async fn count_active_nodes(&self, since: i64) -> Result<i64, StoreError> {
+        let row = self
+            .client()
+            .await?
+            .query_one(
+                "SELECT COUNT(*) FROM nodes n JOIN users u ON u.id = n.user_id
+                 WHERE u.enabled AND n.last_seen_at >= $1",
+                &[&since],
+            )
+            .await?;
+        Ok(row.get(0))
+    }
+
+    async fn record_traffic(&self, samples: &[TrafficSample]) -> Result<(), StoreError> {
+        // Интервал и его вклад во все корзины записываются одним запросом, а все
+        // интерфейсы интервала — одной транзакцией: без владельца не пишется ничего
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        let buckets: Vec<i64> = ROLLUP_BUCKETS.to_vec();
+        for sample in samples {
+            let row = tx
+                .query_opt(
+                    "WITH owner AS (
+                         SELECT id FROM users WHERE username = $1
+                     ),
+                     sample AS (
+                         INSERT INTO traffic_samples
+                             (user_id, node_id, interface, interval_start, interval_end, bytes_sent, bytes_received)
+                         SELECT id, $2, $3, $4, $5, $6, $7 FROM owner
+                         RETURNING user_id
+                     ),
+                     rollup AS (
+                         INSERT INTO traffic_rollups (user_id, node_id, interface, bucket_secs, bucket_start,
+                                                      bytes_sent, bytes_received, samples)
+                         SELECT s.user_id, $2, $3, b.secs, $4 - MOD($4, b.secs), $6, $7, 1
+                         FROM sample s CROSS JOIN UNNEST($8::BIGINT[]) AS b(secs)
+                         ON CONFLICT (user_id, bucket_secs, bucket_start, node_id, interface) DO UPDATE SET
+                             bytes_sent = traffic_rollups.bytes_sent + EXCLUDED.bytes_sent,
+                             bytes_received = traffic_rollups.bytes_received + EXCLUDED.bytes_received,
+                             samples = traffic_rollups.samples + 1
+                     )
+                     SELECT user_id FROM sample",
+                    &[
+                        &sample.username,
+                        &sample.node_id,
+                        &sample.interface,
+                        &sample.interval_start,
+                        &sample.interval_end,
+                        &sample.bytes_sent,
+                        &sample.bytes_received,
+                        &buckets,
+                    ],
+                )
+                .await?;
+            if row.is_none() {
+                return Err(StoreError::UserNotFound(sample.username.clone()));
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn traffic_series(&self, query: &TrafficQuery) -> Result<Vec<TrafficPoint>, StoreError> {
+        let client = self.client().await?;
+        let exists = client.query_opt("SELECT 1 FROM users WHERE username = $1", &[&query.username]).await?;
+        if exists.is_none() {
+            return Err(StoreError::UserNotFound(query.username.clone()));
+        }
+        let rows = match query.bucket_secs {
+            None => {
+                client
+                    .query(
+                        "SELECT s.interval_start, MAX(s.interval_end),
+                                SUM(s.bytes_sent)::BIGINT, SUM(s.bytes_received)::BIGINT
+                         FROM traffic_samples s JOIN users u ON u.id = s.user_id
+                         WHERE u.username = $1 AND s.interval_start >= $2 AND s.interval_start < $3
+                           AND ($4::TEXT IS NULL OR s.node_id = $4) AND ($5::TEXT IS NULL OR s.interface = $5)
+                         GROUP BY s.interval_start ORDER BY s.interval_start",
+                        &[&query.username, &query.from, &query.to, &query.node_id, &query.interface],
+                    )
+                    .await?
+            }
+            Some(bucket) => {
+                client
+                    .query(
+                        "SELECT r.bucket_start, r.bucket_start + $6,
+                                SUM(r.bytes_sent)::BIGINT, SUM(r.bytes_received)::BIGINT
+                         FROM traffic_rollups r JOIN users u ON u.id = r.user_id
+                         WHERE u.username = $1 AND r.bucket_start >= $2 AND r.bucket_start < $3
+                           AND ($4::TEXT IS NULL OR r.node_id = $4) AND ($5::TEXT IS NULL OR r.interface = $5)
+                           AND r.bucket_secs = $6
+                         GROUP BY r.bucket_start ORDER BY r.bucket_start",
+                        &[&query.username, &query.from, &query.to, &query.node_id, &query.interface, &bucket],
+                    )
+                    .await?
+            }
+        };
+        Ok(rows
+            .iter()
+            .map(|row| TrafficPoint {
+                start: row.get(0),
+                end: row.get(1),
+                bytes_sent: row.get(2),
+                bytes_received: row.get(3),
+            })
+            .collect())
+    }
+
+    async fn prune_traffic(
+        &self,
+        samples_before: Option<i64>,
+        rollups_before: &[(i64, i64)],
+    ) -> Result<PrunedTraffic, StoreError> {
+        let client = self.client().await?;
+        let mut pruned = PrunedTraffic::default();
+        if let Some(before) = samples_before {
+            pruned.samples = client.execute("DELETE FROM traffic_samples WHERE interval_end < $1", &[&before]).await?;
+        }
+        for (bucket, before) in rollups_before {
+            pruned.rollups += client
+                .execute("DELETE FROM traffic_rollups WHERE bucket_secs = $1 AND bucket_start < $2", &[bucket, before])
+                .await?;
+        }
+        Ok(pruned)
+    }
+
+    async fn create_account(&self, username: &str, password_hash: &str) -> Result<bool, StoreError> {
+        let created = self
+            .client()
+            .await?
+            .execute(
+                "INSERT INTO users (username, points, password_hash) VALUES ($1, 0, $2)
+                 ON CONFLICT (username) DO NOTHING",
+                &[&username, &password_hash],
+            )
+            .await?;
+        Ok(created > 0)
+    }
+
+    async fn set_password(&self, username: &str, password_hash: &str) -> Result<(), StoreError> {
+        // Один оператор: смена пароля и завершение сессий происходят вместе
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "WITH updated AS (
+                    UPDATE users SET password_hash = $2 WHERE username = $1 RETURNING id
+                 ), revoked AS (
+                    DELETE FROM sessions WHERE user_id IN (SELECT id FROM updated)
+                 )
+                 SELECT id FROM updated",
+                &[&username, &password_hash],
+            )
+            .await?;
+        if row.is_none() {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn set_user_admin(&self, username: &str, is_admin: bool) -> Result<(), StoreError> {
+        let updated = self
+            .client()
+            .await?
+            .execute("UPDATE users SET is_admin = $1 WHERE username = $2", &[&is_admin, &username])
+            .await?;
+        if updated == 0 {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn get_credentials(&self, username: &str) -> Result<Option<Credentials>, StoreError> {
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "SELECT username, password_hash, is_admin, enabled FROM users WHERE username = $1",
+                &[&username],
+            )
+            .await?;
+        Ok(row.map(|row| Credentials {
+            username: row.get(0),
+            password_hash: row.get(1),
+            is_admin: row.get(2),
+            enabled: row.get(3),
+        }))
+    }
+
+    async fn create_session(&self, username: &str, token_hash: &str, expires_at: i64) -> Result<(), StoreError> {
+        let now = unix_now();
+        let client = self.client().await?;
+        client.execute("DELETE FROM sessions WHERE expires_at <= $1", &[&now]).await?;
+        let created = client
+            .execute(
+                "INSERT INTO sessions (token_hash, user_id, created_at, expires_at)
+                 SELECT $1, id, $3, $4 FROM users WHERE username = $2",
+                &[&token_hash, &username, &now, &expires_at],
+            )
+            .await?;
+        if created == 0 {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn get_session(&self, token_hash: &str) -> Result<Option<SessionUser>, StoreError> {
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "SELECT u.username, u.is_admin
+                 FROM sessions s JOIN users u ON u.id = s.user_id
+                 WHERE s.token_hash = $1 AND s.expires_at > $2 AND u.enabled",
+                &[&token_hash, &unix_now()],
+            )
+            .await?;
+        Ok(row.map(|row| SessionUser { username: row.get(0), is_admin: row.get(1) }))
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), StoreError> {
+        self.client().await?.execute("DELETE FROM sessions WHERE token_hash = $1", &[&token_hash]).await?;
+        Ok(())
+    }
+
+    async fn create_api_key(&self, key: &NewApiKey) -> Result<i64, StoreError> {
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "INSERT INTO api_keys (user_id, node_id, name, prefix, key_hash, scopes, created_at)
+                 SELECT id, $2, $3, $4, $5, $6, $7 FROM users WHERE username = $1
+                 RETURNING id",
+                &[&key.username, &key.node_id, &key.name, &key.prefix, &key.key_hash, &key.scopes, &unix_now()],
+            )
+            .await?;
+        row.map(|row| row.get(0)).ok_or_else(|| StoreError::UserNotFound(key.username.clone()))
+    }
+
+    async fn list_api_keys(&self, username: Option<&str>) -> Result<Vec<ApiKey>, StoreError> {
+        let rows = self
+            .client()
+            .await?
+            .query(
+                "SELECT k.id, u.username, k.node_id, k.name, k.prefix, k.scopes,
+                        k.created_at, k.last_used_at, k.revoked_at
+                 FROM api_keys k JOIN users u ON u.id = k.user_id
+                 WHERE $1::TEXT IS NULL OR u.username = $1
+                 ORDER BY k.id DESC",
+                &[&username],
+            )
+            .await?;
+        Ok(rows.iter().map(api_key_from_row).collect())
+    }
+
+    async fn revoke_api_key(&self, id: i64, username: Option<&str>) -> Result<bool, StoreError> {
+        let revoked = self
+            .client()
+            .await?
+            .execute(
+                "UPDATE api_keys SET revoked_at = $3
+                 WHERE id = $1 AND revoked_at IS NULL
+                   AND ($2::TEXT IS NULL OR user_id = (SELECT id FROM users WHERE username = $2))",
+                &[&id, &username, &unix_now()],
+            )
+            .await?;
+        Ok(revoked > 0)
+    }
+
+    async fn authenticate_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyOwner>, StoreError> {
+        // Ключ находится и отмечается использованным одним запросом
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "WITH used AS (
+                     UPDATE api_keys SET last_used_at = $2
+                     WHERE key_hash = $1 AND revoked_at IS NULL
+                     RETURNING id, user_id, node_id, name, prefix, scopes, created_at, last_used_at, revoked_at
+                 )
+                 SELECT k.id, u.username, k.node_id, k.name, k.prefix, k.scopes,
+                        k.created_at, k.last_used_at, k.revoked_at, u.is_admin, u.enabled
+                 FROM used k JOIN users u ON u.id = k.user_id",
+                &[&key_hash, &unix_now()],
+            )
+            .await?;
+        Ok(row.map(|row| ApiKeyOwner { key: api_key_from_row(&row), is_admin: row.get(9), enabled: row.get(10) }))
+    }
+
+    async fn record_config_change(
+        &self,
+        source: &str,
+        node_id: &str,
+        previous: &serde_json::Value,
+        current: &serde_json::Value,
+    ) -> Result<(), StoreError> {
+        self.client()
+            .await?
+            .execute(
+                "INSERT INTO config_audit (changed_at, source, node_id, previous, current)
+                 VALUES ($1, $2, $3, $4, $5)",
+                &[&unix_now(), &source, &node_id, &previous.to_string(), &current.to_string()],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn list_config_changes(&self, limit: i64) -> Result<Vec<ConfigChange>, StoreError> {
+        let rows = self
+            .client()
+            .await?
+            .query(
+                "SELECT id, changed_at, source, node_id, previous, current
+                 FROM config_audit ORDER BY id DESC LIMIT $1",
+                &[&limit],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| ConfigChange {
+                id: row.get(0),
+                changed_at: row.get(1),
+                source: row.get(2),
+                node_id: row.get(3),
+                previous: parse_json(row.get(4)),
+                current: parse_json(row.get(5)),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,917 @@
+// Бэкенд SQLite для одиночных установок без сервера Postgres
+use super::migrations::{AppliedMigration, Migration};
+use super::{
+    parse_json, unix_now, Account, ApiKey, ApiKeyOwner, Binding, ConfigChange, Credentials, DailyEarning,
+    EarningPeriods, Earnings, Enrollment, LastCredit, NewApiKey, NewTransaction, Node, NodeStatus, PointsStore,
+    PrunedTraffic, Reconciliation, SessionUser, StoreError, TrafficDetails, TrafficPoint, TrafficQuery, TrafficSample,
+    Transaction, REASON_OPENING_BALANCE, REASON_TRAFFIC, ROLLUP_BUCKETS,
+};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension, Row, TransactionBehavior};
+use std::sync::{Arc, Mutex};
+
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub async fn open(path: &str) -> Result<Self, StoreError> {
+        let path = path.to_string();
+        let conn = tokio::task::spawn_blocking(move || -> Result<Connection, rusqlite::Error> {
+            let conn = Connection::open(path)?;
+            conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+            Ok(conn)
+        })
+        .await??;
+
+        Ok(SqliteStore { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    // rusqlite блокирующий, поэтому запросы уходят в пул blocking-потоков tokio
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, rusqlite::Error> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        let result = tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().unwrap();
+            f(&mut conn)
+        })
+        .await??;
+        Ok(result)
+    }
+}
+
+// Прибавляет поинты и пишет запись в журнал внутри транзакции вызывающего.
+// `None`, если пользователя нет
+fn credit_in(tx: &rusqlite::Transaction, entry: &NewTransaction) -> Result<Option<i64>, rusqlite::Error> {
+    let credited: Option<(i64, i64)> = tx
+        .query_row(
+            "UPDATE users SET points = points + ?1 WHERE username = ?2 RETURNING id, points",
+            params![entry.points, entry.username],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((user_id, balance)) = credited else {
+        return Ok(None);
+    };
+    let traffic = entry.traffic.as_ref();
+    tx.execute(
+        "INSERT INTO point_transactions
+            (user_id, node_id, interval_start, interval_end, bytes,
+             bytes_sent, bytes_received, upload_threshold, download_threshold,
+             upload_points, download_points, idle_bytes_sent, idle_bytes_received,
+             points, reason, note, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+        params![
+            user_id,
+            entry.node_id,
+            entry.interval_start,
+            entry.interval_end,
+            traffic.map(|t| t.bytes_sent + t.bytes_received),
+            traffic.map(|t| t.bytes_sent),
+            traffic.map(|t| t.bytes_received),
+            traffic.map(|t| t.upload_threshold),
+            traffic.map(|t| t.download_threshold),
+            traffic.map(|t| t.upload_points),
+            traffic.map(|t| t.download_points),
+            traffic.and_then(|t| t.idle_bytes_sent),
+            traffic.and_then(|t| t.idle_bytes_received),
+            entry.points,
+            entry.reason,
+            entry.note,
+            unix_now(),
+        ],
+    )?;
+    Ok(Some(balance))
+}
+
+fn transaction_from_row(row: &Row) -> Result<Transaction, rusqlite::Error> {
+    Ok(Transaction {
+        id: row.get("id")?,
+        node_id: row.get("node_id")?,
+        interval_start: row.get("interval_start")?,
+        interval_end: row.get("interval_end")?,
+        bytes: row.get("bytes")?,
+        threshold: row.get("threshold")?,
+        traffic: match row.get::<_, Option<i64>>("bytes_sent")? {
+            Some(bytes_sent) => Some(TrafficDetails {
+                bytes_sent,
+                bytes_received: row.get::<_, Option<i64>>("bytes_received")?.unwrap_or(0),
+                upload_threshold: row.get::<_, Option<i64>>("upload_threshold")?.unwrap_or(0),
+                download_threshold: row.get::<_, Option<i64>>("download_threshold")?.unwrap_or(0),
+                upload_points: row.get::<_, Option<i64>>("upload_points")?.unwrap_or(0),
+                download_points: row.get::<_, Option<i64>>("download_points")?.unwrap_or(0),
+                idle_bytes_sent: row.get("idle_bytes_sent")?,
+                idle_bytes_received: row.get("idle_bytes_received")?,
+            }),
+            None => None,
+        },
+        points: row.get("points")?,
+        reason: row.get("reason")?,
+        note: row.get("note")?,
+        created_at: row.get("created_at")?,
+    })
+}
+
+fn api_key_from_row(row: &Row) -> Result<ApiKey, rusqlite::Error> {
+    Ok(ApiKey {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        node_id: row.get(2)?,
+        name: row.get(3)?,
+        prefix: row.get(4)?,
+        scopes: row.get::<_, String>(5)?.split_whitespace().map(str::to_string).collect(),
+        created_at: row.get(6)?,
+        last_used_at: row.get(7)?,
+        revoked_at: row.get(8)?,
+    })
+}
+
+#[async_trait]
+impl PointsStore for SqliteStore {
+    async fn ping(&self) -> Result<(), StoreError> {
+        self.with_conn(|conn| conn.query_row("SELECT 1", [], |_| Ok(()))).await
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<AppliedMigration>, StoreError> {
+        self.with_conn(|conn| {
+            let exists: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations')",
+                [],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                return Ok(Vec::new());
+            }
+            let mut stmt =
+                conn.prepare("SELECT version, name, applied_at FROM schema_migrations ORDER BY version")?;
+            let rows = stmt.query_map([], |row| {
+                Ok(AppliedMigration {
+                    version: row.get(0)?,
+                    name: row.get(1)?,
+                    applied_at: row.get(2)?,
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn apply_migration(&self, migration: &Migration) -> Result<bool, StoreError> {
+        let (version, name, sql) = (migration.version, migration.name, migration.sqlite);
+        self.with_conn(move |conn| {
+            // IMMEDIATE сразу берёт блокировку записи: другой процесс ждёт её здесь,
+            // а получив, видит уже применённую версию
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (
+                    version INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    applied_at INTEGER NOT NULL
+                );",
+            )?;
+            let applied = tx
+                .query_row("SELECT 1 FROM schema_migrations WHERE version = ?1", params![version], |_| Ok(()))
+                .optional()?
+                .is_some();
+            if !applied {
+                tx.execute_batch(sql)?;
+                tx.execute(
+                    "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+                    params![version, name, unix_now()],
+                )?;
+            }
+            tx.commit()?;
+            Ok(!applied)
+        })
+        .await
+    }
+
+    async fn add_user(&self, username: &str, points: i64) -> Result<bool, StoreError> {
+        let username = username.to_string();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let inserted = tx.execute(
+                "INSERT INTO users (username, points) VALUES (?1, ?2) ON CONFLICT (username) DO NOTHING",
+                params![username, points],
+            )?;
+            if inserted > 0 && points != 0 {
+                tx.execute(
+                    "INSERT INTO point_transactions (user_id, points, reason, created_at)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![tx.last_insert_rowid(), points, REASON_OPENING_BALANCE, unix_now()],
+                )?;
+            }
+            tx.commit()?;
+            Ok(inserted > 0)
+        })
+        .await
+    }
+
+    async fn list_users(&self) -> Result<Vec<Account>, StoreError> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT username, points, node_id, enabled, is_admin FROM users ORDER BY id")?;
+            let rows = stmt.query_map([], |row| {
+                Ok(Account {
+                    username: row.get(0)?,
+                    points: row.get(1)?,
+                    node_id: row.get(2)?,
+                    enabled: row.get(3)?,
+                    is_admin: row.get(4)?,
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn monitored_users(&self, node_id: &str) -> Result<Vec<String>, StoreError> {
+        let node_id = node_id.to_string();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT username FROM users
+                 WHERE enabled AND node_id = ?1
+                 ORDER BY id",
+            )?;
+            let rows = stmt.query_map(params![node_id], |row| row.get(0))?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn set_user_enabled(&self, username: &str, enabled: bool) -> Result<(), StoreError> {
+        let name = username.to_string();
+        let updated = self
+            .with_conn(move |conn| {
+                let tx = conn.transaction()?;
+                let updated = tx.execute("UPDATE users SET enabled = ?1 WHERE username = ?2", params![enabled, name])?;
+                // Отключённый аккаунт выходит из всех сессий сразу
+                if !enabled {
+                    tx.execute(
+                        "DELETE FROM sessions WHERE user_id = (SELECT id FROM users WHERE username = ?1)",
+                        params![name],
+                    )?;
+                }
+                tx.commit()?;
+                Ok(updated)
+            })
+            .await?;
+        if updated == 0 {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn set_user_node(&self, username: &str, node_id: Option<&str>) -> Result<Binding, StoreError> {
+        let (name, node_id) = (username.to_string(), node_id.map(str::to_string));
+        let binding = self
+            .with_conn(move |conn| {
+                let tx = conn.transaction()?;
+                let Some(user_id): Option<i64> = tx
+                    .query_row("SELECT id FROM users WHERE username = ?1", params![name], |row| row.get(0))
+                    .optional()?
+                else {
+                    return Ok(None);
+                };
+                let owned: Option<String> = tx
+                    .query_row("SELECT id FROM nodes WHERE user_id = ?1", params![user_id], |row| row.get(0))
+                    .optional()?;
+                if owned.is_some() && owned != node_id {
+                    return Ok(Some(Binding::OwnsNode));
+                }
+                let taken = tx
+                    .query_row(
+                        "SELECT 1 FROM users WHERE node_id = ?1 AND id <> ?2
+                         UNION ALL SELECT 1 FROM nodes WHERE id = ?1 AND user_id <> ?2",
+                        params![node_id, user_id],
+                        |_| Ok(()),
+                    )
+                    .optional()?;
+                if taken.is_some() {
+                    return Ok(Some(Binding::NodeTaken));
+                }
+                tx.execute("UPDATE users SET node_id = ?1 WHERE id = ?2", params![node_id, user_id])?;
+                tx.commit()?;
+                Ok(Some(Binding::Bound))
+            })
+            .await?;
+        binding.ok_or_else(|| StoreError::UserNotFound(username.to_string()))
+    }
+
+    async fn delete_user(&self, username: &str) -> Result<(), StoreError> {
+        let name = username.to_string();
+        let deleted = self
+            .with_conn(move |conn| conn.execute("DELETE FROM users WHERE username = ?1", params![name]))
+            .await?;
+        if deleted == 0 {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn create_node(&self, node_id: &str, username: &str, secret: &str) -> Result<Enrollment, StoreError> {
+        let (node, name, secret) = (node_id.to_string(), username.to_string(), secret.to_string());
+        let enrollment = self
+            .with_conn(move |conn| {
+                let tx = conn.transaction()?;
+                let Some((user_id, bound_to)): Option<(i64, Option<String>)> = tx
+                    .query_row("SELECT id, node_id FROM users WHERE username = ?1", params![name], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })
+                    .optional()?
+                else {
+                    return Ok(None);
+                };
+                if bound_to.is_some() {
+                    return Ok(Some(Enrollment::AccountBound));
+                }
+                let created = tx.execute(
+                    "INSERT INTO nodes (id, user_id, secret, enrolled_at) VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT (id) DO NOTHING",
+                    params![node, user_id, secret, unix_now()],
+                )? > 0;
+                if !created {
+                    return Ok(Some(Enrollment::NodeTaken));
+                }
+                tx.execute("UPDATE users SET node_id = ?1 WHERE id = ?2", params![node, user_id])?;
+                tx.commit()?;
+                Ok(Some(Enrollment::Created))
+            })
+            .await?;
+        enrollment.ok_or_else(|| StoreError::UserNotFound(username.to_string()))
+    }
+
+    async fn get_node(&self, node_id: &str) -> Result<Option<Node>, StoreError> {
+        let node_id = node_id.to_string();
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT n.id, u.username, u.enabled, n.secret, n.enrolled_at
+                 FROM nodes n JOIN users u ON u.id = n.user_id
+                 WHERE n.id = ?1",
+                params![node_id],
+                |row| {
+                    Ok(Node {
+                        id: row.get(0)?,
+                        username: row.get(1)?,
+                        user_enabled: row.get(2)?,
+                        secret: row.get(3)?,
+                        enrolled_at: row.get(4)?,
+                    })
+                },
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn accept_node_report(
+        &self,
+        node_id: &str,
+        interval_start: i64,
+        interval_end: i64,
+        credit: Option<&NewTransaction>,
+    ) -> Result<Option<i64>, StoreError> {
+        let (node_id, credit) = (node_id.to_string(), credit.cloned());
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let accepted = tx.execute(
+                "UPDATE nodes SET last_report_end = ?3, last_seen_at = ?4
+                 WHERE id = ?1 AND (last_report_end IS NULL OR last_report_end <= ?2)",
+                params![node_id, interval_start, interval_end, unix_now()],
+            )? > 0;
+            if !accepted {
+                return Ok(Err(StoreError::ReportOverlap));
+            }
+            let balance = match credit {
+                Some(entry) => match credit_in(&tx, &entry)? {
+                    Some(balance) => Some(balance),
+                    None => return Ok(Err(StoreError::UserNotFound(entry.username))),
+                },
+                None => None,
+            };
+            tx.commit()?;
+            Ok(Ok(balance))
+        })
+        .await?
+    }
+
+    async fn get_user_points(&self, username: &str) -> Result<i64, StoreError> {
+        let name = username.to_string();
+        self.with_conn(move |conn| {
+            conn.query_row("SELECT points FROM users WHERE username = ?1", params![name], |row| row.get(0))
+                .optional()
+        })
+        .await?
+        .ok_or_else(|| StoreError::UserNotFound(username.to_string()))
+    }
+
+    async fn credit_points(&self, entry: &NewTransaction) -> Result<i64, StoreError> {
+        let entry = entry.clone();
+        let username = entry.username.clone();
+        let balance = self
+            .with_conn(move |conn| {
+                let tx = conn.transaction()?;
+                let balance = credit_in(&tx, &entry)?;
+                tx.commit()?;
+                Ok(balance)
+            })
+            .await?;
+        balance.ok_or(StoreError::UserNotFound(username))
+    }
+
+    async fn list_transactions(
+        &self,
+        username: &str,
+        limit: i64,
+        before: Option<i64>,
+    ) -> Result<Vec<Transaction>, StoreError> {
+        let username = username.to_string();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT t.id, t.node_id, t.interval_start, t.interval_end, t.bytes, t.threshold,
+                        t.bytes_sent, t.bytes_received, t.upload_threshold, t.download_threshold,
+                        t.upload_points, t.download_points, t.idle_bytes_sent, t.idle_bytes_received,
+                        t.points, t.reason, t.note, t.created_at
+                 FROM point_transactions t JOIN users u ON u.id = t.user_id
+                 WHERE u.username = ?1 AND (?2 IS NULL OR t.id < ?2)
+                 ORDER BY t.id DESC
+                 LIMIT ?3",
+            )?;
+            let rows = stmt.query_map(params![username, before, limit], transaction_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn reconcile(&self, username: &str) -> Result<Reconciliation, StoreError> {
+        let name = username.to_string();
+        let row = self
+            .with_conn(move |conn| {
+                conn.query_row(
+                    "SELECT u.points,
+                            COALESCE((SELECT SUM(points) FROM point_transactions WHERE user_id = u.id), 0)
+                     FROM users u WHERE u.username = ?1",
+                    params![name],
+                    |row| Ok(Reconciliation { balance: row.get(0)?, ledger_balance: row.get(1)? }),
+                )
+                .optional()
+            })
+            .await?;
+        row.ok_or_else(|| StoreError::UserNotFound(username.to_string()))
+    }
+
+    async fn earnings(&self, username: &str, periods: &EarningPeriods) -> Result<Earnings, StoreError> {
+        let (name, periods) = (username.to_string(), *periods);
+        let earnings = self
+            .with_conn(move |conn| {
+                let totals = conn
+                    .query_row(
+                        "SELECT COALESCE(SUM(CASE WHEN t.created_at >= ?2 THEN t.points ELSE 0 END), 0),
+                                COALESCE(SUM(CASE WHEN t.created_at >= ?3 THEN t.points ELSE 0 END), 0),
+                                COALESCE(SUM(CASE WHEN t.created_at >= ?4 THEN t.points ELSE 0 END), 0),
+                                COALESCE(SUM(t.points), 0)
+                         FROM users u
+                         LEFT JOIN point_transactions t ON t.user_id = u.id AND t.reason = ?5
+                         WHERE u.username = ?1
+                         GROUP BY u.id",
+                        params![name, periods.today, periods.last_7_days, periods.last_30_days, REASON_TRAFFIC],
+                        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                    )
+                    .optional()?;
+                let Some((today, last_7_days, last_30_days, all_time)) = totals else {
+                    return Ok(None);
+                };
+                let last_credit = conn
+                    .query_row(
+                        "SELECT t.node_id, t.interval_start, t.interval_end, t.points, t.created_at
+                         FROM point_transactions t JOIN users u ON u.id = t.user_id
+                         WHERE u.username = ?1 AND t.reason = ?2
+                         ORDER BY t.id DESC LIMIT 1",
+                        params![name, REASON_TRAFFIC],
+                        |row| {
+                            Ok(LastCredit {
+                                node_id: row.get(0)?,
+                                interval_start: row.get(1)?,
+                                interval_end: row.get(2)?,
+                                points: row.get(3)?,
+                                created_at: row.get(4)?,
+                            })
+                        },
+                    )
+                    .optional()?;
+                Ok(Some(Earnings { today, last_7_days, last_30_days, all_time, last_credit }))
+            })
+            .await?;
+        earnings.ok_or_else(|| StoreError::UserNotFound(username.to_string()))
+    }
+
+    async fn daily_earnings(
+        &self,
+        username: &str,
+        since: i64,
+        utc_offset: i64,
+    ) -> Result<Vec<DailyEarning>, StoreError> {
+        let name = username.to_string();
+        let days = self
+            .with_conn(move |conn| {
+                let user_id: Option<i64> = conn
+                    .query_row("SELECT id FROM users WHERE username = ?1", params![name], |row| row.get(0))
+                    .optional()?;
+                let Some(user_id) = user_id else {
+                    return Ok(None);
+                };
+                let mut stmt = conn.prepare(
+                    "SELECT (created_at + ?3) / 86400 * 86400 - ?3 AS day_start, SUM(points)
+                     FROM point_transactions
+                     WHERE user_id = ?1 AND reason = ?4 AND created_at >= ?2
+                     GROUP BY day_start
+                     ORDER BY day_start",
+                )?;
+                let rows = stmt.query_map(params![user_id, since, utc_offset, REASON_TRAFFIC], |row| {
+                    Ok(DailyEarning { day_start: row.get(0)?, points: row.get(1)? })
+                })?;
+                rows.collect::<Result<Vec<_>, _>>().map(Some)
+            })
+            .await?;
+        days.ok_or_else(|| StoreError::UserNotFound(username.to_string()))
+    }
+
+    async fn node_status(&self, username: &str) -> Result<NodeStatus, StoreError> {
+        let name = username.to_string();
+        let status = self
+            .with_conn(move |conn| {
+                conn.query_row(
+                    "SELECT u.node_id, u.enabled, n.last_seen_at
+                     FROM users u LEFT JOIN nodes n ON n.id = u.node_id
+                     WHERE u.username = ?1",
+                    params![name],
+                    |row| Ok(NodeStatus { node_id: row.get(0)?, enabled: row.get(1)?, last_seen_at: row.get(2)? }),
+                )
+                .optional()
+            })
+            .await?;
+        status.ok_or_else(|| StoreError::UserNotFound(username.to_string()))
+    }
+
+    async fn count_active_nodes(&self, since: i64) -> Result<i64, StoreError> {
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM nodes n JOIN users u ON u.id = n.user_id
+                 WHERE u.enabled AND n.last_seen_at >= ?1",
+                params![since],
+                |row| row.get(0),
+            )
+        })
+        .await
+    }
+
+    async fn record_traffic(&self, samples: &[TrafficSample]) -> Result<(), StoreError> {
+        let samples = samples.to_vec();
+        let missing = self
+            .with_conn(move |conn| {
+                let tx = conn.transaction()?;
+                for sample in &samples {
+                    let Some(user_id): Option<i64> = tx
+                        .query_row("SELECT id FROM users WHERE username = ?1", params![sample.username], |row| row.get(0))
+                        .optional()?
+                    else {
+                        return Ok(Some(sample.username.clone()));
+                    };
+                    tx.execute(
+                        "INSERT INTO traffic_samples
+                             (user_id, node_id, interface, interval_start, interval_end, bytes_sent, bytes_received)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                        params![
+                            user_id,
+                            sample.node_id,
+                            sample.interface,
+                            sample.interval_start,
+                            sample.interval_end,
+                            sample.bytes_sent,
+                            sample.bytes_received
+                        ],
+                    )?;
+                    for bucket in ROLLUP_BUCKETS {
+                        tx.execute(
+                            "INSERT INTO traffic_rollups (user_id, node_id, interface, bucket_secs, bucket_start,
+                                                          bytes_sent, bytes_received, samples)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 1)
+                             ON CONFLICT (user_id, bucket_secs, bucket_start, node_id, interface) DO UPDATE SET
+                                 bytes_sent = traffic_rollups.bytes_sent + excluded.bytes_sent,
+                                 bytes_received = traffic_rollups.bytes_received + excluded.bytes_received,
+                                 samples = traffic_rollups.samples + 1",
+                            params![
+                                user_id,
+                                sample.node_id,
+                                sample.interface,
+                                bucket,
+                                sample.interval_start - sample.interval_start.rem_euclid(bucket),
+                                sample.bytes_sent,
+                                sample.bytes_received
+                            ],
+                        )?;
+                    }
+                }
+                tx.commit()?;
+                Ok(None)
+            })
+            .await?;
+        match missing {
+            Some(username) => Err(StoreError::UserNotFound(username)),
+            None => Ok(()),
+        }
+    }
+
+    async fn traffic_series(&self, query: &TrafficQuery) -> Result<Vec<TrafficPoint>, StoreError> {
+        let query = query.clone();
+        let username = query.username.clone();
+        let series = self
+            .with_conn(move |conn| {
+                let exists = conn
+                    .query_row("SELECT 1 FROM users WHERE username = ?1", params![query.username], |_| Ok(()))
+                    .optional()?;
+                if exists.is_none() {
+                    return Ok(None);
+                }
+                let point = |row: &Row| {
+                    Ok(TrafficPoint {
+                        start: row.get(0)?,
+                        end: row.get(1)?,
+                        bytes_sent: row.get(2)?,
+                        bytes_received: row.get(3)?,
+                    })
+                };
+                let filters = params![query.username, query.from, query.to, query.node_id, query.interface];
+                let rows = match query.bucket_secs {
+                    None => conn
+                        .prepare(
+                            "SELECT s.interval_start, MAX(s.interval_end), SUM(s.bytes_sent), SUM(s.bytes_received)
+                             FROM traffic_samples s JOIN users u ON u.id = s.user_id
+                             WHERE u.username = ?1 AND s.interval_start >= ?2 AND s.interval_start < ?3
+                               AND (?4 IS NULL OR s.node_id = ?4) AND (?5 IS NULL OR s.interface = ?5)
+                             GROUP BY s.interval_start ORDER BY s.interval_start",
+                        )?
+                        .query_map(filters, point)?
+                        .collect::<Result<Vec<_>, _>>(),
+                    Some(bucket) => conn
+                        .prepare(
+                            "SELECT r.bucket_start, r.bucket_start + ?6, SUM(r.bytes_sent), SUM(r.bytes_received)
+                             FROM traffic_rollups r JOIN users u ON u.id = r.user_id
+                             WHERE u.username = ?1 AND r.bucket_start >= ?2 AND r.bucket_start < ?3
+                               AND (?4 IS NULL OR r.node_id = ?4) AND (?5 IS NULL OR r.interface = ?5)
+                               AND r.bucket_secs = ?6
+                             GROUP BY r.bucket_start ORDER BY r.bucket_start",
+                        )?
+                        .query_map(
+                            params![query.username, query.from, query.to, query.node_id, query.interface, bucket],
+                            point,
+                        )?
+                        .collect::<Result<Vec<_>, _>>(),
+                };
+                rows.map(Some)
+            })
+            .await?;
+        series.ok_or(StoreError::UserNotFound(username))
+    }
+
+    async fn prune_traffic(
+        &self,
+        samples_before: Option<i64>,
+        rollups_before: &[(i64, i64)],
+    ) -> Result<PrunedTraffic, StoreError> {
+        let rollups_before = rollups_before.to_vec();
+        self.with_conn(move |conn| {
+            let mut pruned = PrunedTraffic::default();
+            if let Some(before) = samples_before {
+                pruned.samples = conn.execute("DELETE FROM traffic_samples WHERE interval_end < ?1", params![before])? as u64;
+            }
+            for (bucket, before) in rollups_before {
+                pruned.rollups += conn.execute(
+                    "DELETE FROM traffic_rollups WHERE bucket_secs = ?1 AND bucket_start < ?2",
+                    params![bucket, before],
+                )? as u64;
+            }
+            Ok(pruned)
+        })
+        .await
+    }
+
+    async fn create_account(&self, username: &str, password_hash: &str) -> Result<bool, StoreError> {
+        let (name, hash) = (username.to_string(), password_hash.to_string());
+        let created = self
+            .with_conn(move |conn| {
+                conn.execute(
+                    "INSERT INTO users (username, points, password_hash) VALUES (?1, 0, ?2)
+                     ON CONFLICT (username) DO NOTHING",
+                    params![name, hash],
+                )
+            })
+            .await?;
+        Ok(created > 0)
+    }
+
+    async fn set_password(&self, username: &str, password_hash: &str) -> Result<(), StoreError> {
+        let (name, hash) = (username.to_string(), password_hash.to_string());
+        let updated = self
+            .with_conn(move |conn| {
+                let tx = conn.transaction()?;
+                let updated =
+                    tx.execute("UPDATE users SET password_hash = ?1 WHERE username = ?2", params![hash, name])?;
+                tx.execute(
+                    "DELETE FROM sessions WHERE user_id = (SELECT id FROM users WHERE username = ?1)",
+                    params![name],
+                )?;
+                tx.commit()?;
+                Ok(updated)
+            })
+            .await?;
+        if updated == 0 {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn set_user_admin(&self, username: &str, is_admin: bool) -> Result<(), StoreError> {
+        let name = username.to_string();
+        let updated = self
+            .with_conn(move |conn| {
+                conn.execute("UPDATE users SET is_admin = ?1 WHERE username = ?2", params![is_admin, name])
+            })
+            .await?;
+        if updated == 0 {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn get_credentials(&self, username: &str) -> Result<Option<Credentials>, StoreError> {
+        let name = username.to_string();
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT username, password_hash, is_admin, enabled FROM users WHERE username = ?1",
+                params![name],
+                |row| {
+                    Ok(Credentials {
+                        username: row.get(0)?,
+                        password_hash: row.get(1)?,
+                        is_admin: row.get(2)?,
+                        enabled: row.get(3)?,
+                    })
+                },
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn create_session(&self, username: &str, token_hash: &str, expires_at: i64) -> Result<(), StoreError> {
+        let (name, hash) = (username.to_string(), token_hash.to_string());
+        let created = self
+            .with_conn(move |conn| {
+                let now = unix_now();
+                conn.execute("DELETE FROM sessions WHERE expires_at <= ?1", params![now])?;
+                conn.execute(
+                    "INSERT INTO sessions (token_hash, user_id, created_at, expires_at)
+                     SELECT ?1, id, ?3, ?4 FROM users WHERE username = ?2",
+                    params![hash, name, now, expires_at],
+                )
+            })
+            .await?;
+        if created == 0 {
+            return Err(StoreError::UserNotFound(username.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn get_session(&self, token_hash: &str) -> Result<Option<SessionUser>, StoreError> {
+        let hash = token_hash.to_string();
+        self.with_conn(move |conn| {
+            conn.query_row(
+                "SELECT u.username, u.is_admin
+                 FROM sessions s JOIN users u ON u.id = s.user_id
+                 WHERE s.token_hash = ?1 AND s.expires_at > ?2 AND u.enabled",
+                params![hash, unix_now()],
+                |row| Ok(SessionUser { username: row.get(0)?, is_admin: row.get(1)? }),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), StoreError> {
+        let hash = token_hash.to_string();
+        self.with_conn(move |conn| conn.execute("DELETE FROM sessions WHERE token_hash = ?1", params![hash]).map(|_| ()))
+            .await
+    }
+
+    async fn create_api_key(&self, key: &NewApiKey) -> Result<i64, StoreError> {
+        let key = key.clone();
+        let username = key.username.clone();
+        let id = self
+            .with_conn(move |conn| {
+                let created = conn.execute(
+                    "INSERT INTO api_keys (user_id, node_id, name, prefix, key_hash, scopes, created_at)
+                     SELECT id, ?2, ?3, ?4, ?5, ?6, ?7 FROM users WHERE username = ?1",
+                    params![key.username, key.node_id, key.name, key.prefix, key.key_hash, key.scopes, unix_now()],
+                )?;
+                Ok((created > 0).then(|| conn.last_insert_rowid()))
+            })
+            .await?;
+        id.ok_or(StoreError::UserNotFound(username))
+    }
+
+    async fn list_api_keys(&self, username: Option<&str>) -> Result<Vec<ApiKey>, StoreError> {
+        let name = username.map(str::to_string);
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT k.id, u.username, k.node_id, k.name, k.prefix, k.scopes,
+                        k.created_at, k.last_used_at, k.revoked_at
+                 FROM api_keys k JOIN users u ON u.id = k.user_id
+                 WHERE ?1 IS NULL OR u.username = ?1
+                 ORDER BY k.id DESC",
+            )?;
+            let rows = stmt.query_map(params![name], api_key_from_row)?;
+            rows.collect()
+        })
+        .await
+    }
+
+    async fn revoke_api_key(&self, id: i64, username: Option<&str>) -> Result<bool, StoreError> {
+        let name = username.map(str::to_string);
+        let revoked = self
+            .with_conn(move |conn| {
+                conn.execute(
+                    "UPDATE api_keys SET revoked_at = ?3
+                     WHERE id = ?1 AND revoked_at IS NULL
+                       AND (?2 IS NULL OR user_id = (SELECT id FROM users WHERE username = ?2))",
+                    params![id, name, unix_now()],
+                )
+            })
+            .await?;
+        Ok(revoked > 0)
+    }
+
+    async fn authenticate_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyOwner>, StoreError> {
+        let hash = key_hash.to_string();
+        self.with_conn(move |conn| {
+            let owner = conn
+                .query_row(
+                    "SELECT k.id, u.username, k.node_id, k.name, k.prefix, k.scopes,
+                            k.created_at, k.last_used_at, k.revoked_at, u.is_admin, u.enabled
+                     FROM api_keys k JOIN users u ON u.id = k.user_id
+                     WHERE k.key_hash = ?1 AND k.revoked_at IS NULL",
+                    params![hash],
+                    |row| Ok(ApiKeyOwner { key: api_key_from_row(row)?, is_admin: row.get(9)?, enabled: row.get(10)? }),
+                )
+                .optional()?;
+            if let Some(owner) = &owner {
+                conn.execute("UPDATE api_keys SET last_used_at = ?2 WHERE id = ?1", params![owner.key.id, unix_now()])?;
+            }
+            Ok(owner)
+        })
+        .await
+    }
+
+    async fn record_config_change(
+        &self,
+        source: &str,
+        node_id: &str,
+        previous: &serde_json::Value,
+        current: &serde_json::Value,
+    ) -> Result<(), StoreError> {
+        let (source, node_id) = (source.to_string(), node_id.to_string());
+        let (previous, current) = (previous.to_string(), current.to_string());
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO config_audit (changed_at, source, node_id, previous, current)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![unix_now(), source, node_id, previous, current],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+
+    async fn list_config_changes(&self, limit: i64) -> Result<Vec<ConfigChange>, StoreError> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, changed_at, source, node_id, previous, current
+                 FROM config_audit ORDER BY id DESC LIMIT ?1",
+            )?;
+            let rows = stmt.query_map(params![limit], |row| {
+                Ok(ConfigChange {
+                    id: row.get(0)?,
+                    changed_at: row.get(1)?,
+                    source: row.get(2)?,
+                    node_id: row.get(3)?,
+                    previous: parse_json(row.get(4)?),
+                    current: parse_json(row.get(5)?),
+                })
+            })?;
+            rows.collect()
+        })
+        .await
+    }
+}
@@ -0,0 +1,225 @@
+// Общие тесты хранилищ. Каждый прогоняется на SQLite в памяти и, если задана
+// переменная TEST_DATABASE_URL, на PostgreSQL. Схема этой базы удаляется перед
+// каждым тестом, поэтому указывать в ней можно только отдельную тестовую базу.
+use super::*;
+use tokio::sync::{Mutex, MutexGuard};
+
+const TEST_DATABASE_URL: &str = "TEST_DATABASE_URL";
+
+// Тесты на PostgreSQL делят одну базу и поэтому идут по одному
+static POSTGRES: Mutex<()> = Mutex::const_new(());
+
+async fn sqlite_store() -> SqliteStore {
+    SqliteStore::open(":memory:").await.unwrap()
+}
+
+// Пустая схема PostgreSQL и блокировка базы на время теста; `None`, если база не задана
+async fn postgres_store() -> Option<(PostgresStore, MutexGuard<'static, ()>)> {
+    let url = std::env::var(TEST_DATABASE_URL).ok()?;
+    let guard = POSTGRES.lock().await;
+    let store = PostgresStore::connect(&url, &PoolOptions::default()).await.unwrap();
+    store.drop_schema().await.unwrap();
+    Some((store, guard))
+}
+
+async fn migrated(store: &dyn PointsStore) {
+    migrations::run(store, false).await.unwrap();
+}
+
+// Каждый тест из списка — асинхронная функция от хранилища с применёнными миграциями
+macro_rules! store_tests {
+    ($($name:ident),* $(,)?) => {
+        mod on_sqlite {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    let store = super::sqlite_store().await;
+                    super::migrated(&store).await;
+                    super::$name(&store).await;
+                }
+            )*
+        }
+
+        mod on_postgres {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    let Some((store, _guard)) = super::postgres_store().await else {
+                        return;
+                    };
+                    super::migrated(&store).await;
+                    super::$name(&store).await;
+                }
+            )*
+        }
+    };
+}
+
+store_tests!(
+    monitors_only_accounts_bound_to_the_node,
+    binding_keeps_one_account_per_node,
+    adding_an_existing_user_changes_nothing,
+    opening_balance_is_recorded_in_the_ledger,
+    enrollment_does_not_rebind_accounts,
+    concurrent_enrollments_bind_an_account_once,
+    failed_credit_leaves_report_retryable,
+    credit_updates_balance_and_ledger_together,
+    disabling_an_account_ends_its_sessions,
+    balance_of_unknown_user_is_not_found,
+);
+
+async fn check_all_migrations_apply(store: &dyn PointsStore) {
+    let applied = migrations::run(store, false).await.unwrap();
+    assert_eq!(applied.len(), migrations::MIGRATIONS.len());
+
+    let status = migrations::status(store).await.unwrap();
+    assert!(status.migrations.iter().all(|m| m.applied_at.is_some()));
+    assert!(status.unknown.is_empty());
+    // Повторный запуск и опоздавший процесс ничего не применяют второй раз
+    assert!(migrations::run(store, false).await.unwrap().is_empty());
+    assert!(!store.apply_migration(&migrations::MIGRATIONS[0]).await.unwrap());
+}
+
+#[tokio::test]
+async fn all_migrations_apply_cleanly_on_sqlite() {
+    check_all_migrations_apply(&sqlite_store().await).await;
+}
+
+#[tokio::test]
+async fn all_migrations_apply_cleanly_on_postgres() {
+    if let Some((store, _guard)) = postgres_store().await {
+        check_all_migrations_apply(&store).await;
+    }
+}
+
+#[tokio::test]
+async fn concurrent_migrations_apply_each_version_once_on_postgres() {
+    let Some((first, _guard)) = postgres_store().await else {
+        return;
+    };
+    let url = std::env::var(TEST_DATABASE_URL).unwrap();
+    let second = PostgresStore::connect(&url, &PoolOptions::default()).await.unwrap();
+    let (a, b) = tokio::join!(migrations::run(&first, false), migrations::run(&second, false));
+    assert_eq!(a.unwrap().len() + b.unwrap().len(), migrations::MIGRATIONS.len());
+    assert!(migrations::status(&first).await.unwrap().migrations.iter().all(|m| m.applied_at.is_some()));
+}
+
+async fn monitors_only_accounts_bound_to_the_node(store: &dyn PointsStore) {
+    // Аккаунт из самостоятельной регистрации не привязан ни к какой ноде
+    assert!(store.create_account("mallory", "hash").await.unwrap());
+    assert!(store.monitored_users("srv").await.unwrap().is_empty());
+
+    store.add_user("alice", 0).await.unwrap();
+    store.set_user_node("alice", Some("srv")).await.unwrap();
+    assert_eq!(store.monitored_users("srv").await.unwrap(), ["alice"]);
+    assert!(store.monitored_users("other").await.unwrap().is_empty());
+}
+
+async fn binding_keeps_one_account_per_node(store: &dyn PointsStore) {
+    for name in ["alice", "bob", "carol"] {
+        store.add_user(name, 0).await.unwrap();
+    }
+    assert_eq!(store.set_user_node("alice", Some("srv")).await.unwrap(), Binding::Bound);
+    assert_eq!(store.set_user_node("bob", Some("srv")).await.unwrap(), Binding::NodeTaken);
+    assert_eq!(store.create_node("n1", "carol", "secret").await.unwrap(), Enrollment::Created);
+    assert_eq!(store.set_user_node("bob", Some("n1")).await.unwrap(), Binding::NodeTaken);
+    // Владелец ноды-агента остаётся на ней: отчёты всё равно зачисляются ему
+    assert_eq!(store.set_user_node("carol", Some("srv2")).await.unwrap(), Binding::OwnsNode);
+    assert_eq!(store.set_user_node("carol", None).await.unwrap(), Binding::OwnsNode);
+    assert_eq!(store.set_user_node("carol", Some("n1")).await.unwrap(), Binding::Bound);
+    assert_eq!(store.set_user_node("alice", None).await.unwrap(), Binding::Bound);
+    assert_eq!(store.set_user_node("bob", Some("srv")).await.unwrap(), Binding::Bound);
+    assert!(matches!(store.set_user_node("dave", None).await, Err(StoreError::UserNotFound(_))));
+}
+
+async fn adding_an_existing_user_changes_nothing(store: &dyn PointsStore) {
+    assert!(store.add_user("alice", 5).await.unwrap());
+    assert!(!store.add_user("alice", 7).await.unwrap());
+    assert_eq!(store.get_user_points("alice").await.unwrap(), 5);
+}
+
+async fn opening_balance_is_recorded_in_the_ledger(store: &dyn PointsStore) {
+    store.add_user("alice", 5).await.unwrap();
+    store.add_user("bob", 0).await.unwrap();
+    let ledger = store.list_transactions("alice", 10, None).await.unwrap();
+    assert_eq!(ledger.len(), 1);
+    assert_eq!((ledger[0].points, ledger[0].reason.as_str()), (5, REASON_OPENING_BALANCE));
+    assert!(store.list_transactions("bob", 10, None).await.unwrap().is_empty());
+}
+
+async fn enrollment_does_not_rebind_accounts(store: &dyn PointsStore) {
+    store.add_user("alice", 0).await.unwrap();
+    store.add_user("bob", 0).await.unwrap();
+    assert_eq!(store.create_node("n1", "alice", "secret").await.unwrap(), Enrollment::Created);
+    assert_eq!(store.create_node("n2", "alice", "secret").await.unwrap(), Enrollment::AccountBound);
+    assert_eq!(store.create_node("n1", "bob", "secret").await.unwrap(), Enrollment::NodeTaken);
+    assert!(store.get_node("n2").await.unwrap().is_none());
+    assert_eq!(store.get_node("n1").await.unwrap().unwrap().username, "alice");
+    assert_eq!(store.monitored_users("n1").await.unwrap(), ["alice"]);
+    assert!(matches!(store.create_node("n3", "nobody", "secret").await, Err(StoreError::UserNotFound(_))));
+}
+
+async fn concurrent_enrollments_bind_an_account_once(store: &dyn PointsStore) {
+    store.add_user("alice", 0).await.unwrap();
+    let (a, b) = tokio::join!(store.create_node("n1", "alice", "secret"), store.create_node("n2", "alice", "secret"));
+    let mut outcomes = [a.unwrap(), b.unwrap()];
+    outcomes.sort_by_key(|outcome| *outcome != Enrollment::Created);
+    assert_eq!(outcomes, [Enrollment::Created, Enrollment::AccountBound]);
+}
+
+fn traffic_credit(username: &str, start: i64, points: i64) -> NewTransaction {
+    NewTransaction {
+        username: username.to_string(),
+        node_id: Some("n1".to_string()),
+        interval_start: Some(start),
+        interval_end: Some(start + 30),
+        traffic: None,
+        points,
+        reason: REASON_TRAFFIC.to_string(),
+        note: None,
+    }
+}
+
+async fn failed_credit_leaves_report_retryable(store: &dyn PointsStore) {
+    store.add_user("alice", 0).await.unwrap();
+    assert_eq!(store.create_node("n1", "alice", "secret").await.unwrap(), Enrollment::Created);
+
+    // Начисление не прошло — отметка отчёта откатывается вместе с ним
+    let failed = store.accept_node_report("n1", 100, 130, Some(&traffic_credit("nobody", 100, 5))).await;
+    assert!(matches!(failed, Err(StoreError::UserNotFound(_))));
+    let entry = traffic_credit("alice", 100, 5);
+    assert_eq!(store.accept_node_report("n1", 100, 130, Some(&entry)).await.unwrap(), Some(5));
+
+    let replay = store.accept_node_report("n1", 100, 130, Some(&entry)).await;
+    assert!(matches!(replay, Err(StoreError::ReportOverlap)));
+    assert_eq!(store.accept_node_report("n1", 130, 160, None).await.unwrap(), None);
+    assert_eq!(store.get_user_points("alice").await.unwrap(), 5);
+}
+
+async fn credit_updates_balance_and_ledger_together(store: &dyn PointsStore) {
+    store.add_user("alice", 0).await.unwrap();
+    assert_eq!(store.credit_points(&traffic_credit("alice", 0, 3)).await.unwrap(), 3);
+    assert_eq!(store.credit_points(&traffic_credit("alice", 30, 4)).await.unwrap(), 7);
+    assert!(matches!(store.credit_points(&traffic_credit("nobody", 0, 1)).await, Err(StoreError::UserNotFound(_))));
+
+    let reconciliation = store.reconcile("alice").await.unwrap();
+    assert_eq!((reconciliation.balance, reconciliation.ledger_balance), (7, 7));
+    assert_eq!(store.list_transactions("alice", 10, None).await.unwrap().len(), 2);
+}
+
+async fn disabling_an_account_ends_its_sessions(store: &dyn PointsStore) {
+    assert!(store.create_account("alice1", "hash").await.unwrap());
+    store.create_session("alice1", "token", unix_now() + 60).await.unwrap();
+    assert!(store.get_session("token").await.unwrap().is_some());
+
+    store.set_user_enabled("alice1", false).await.unwrap();
+    assert!(store.get_session("token").await.unwrap().is_none());
+    assert!(!store.get_credentials("alice1").await.unwrap().unwrap().enabled);
+    // Включение не возвращает старые сессии
+    store.set_user_enabled("alice1", true).await.unwrap();
+    assert!(store.get_session("token").await.unwrap().is_none());
+}
+
+async fn balance_of_unknown_user_is_not_found(store: &dyn PointsStore) {
+    assert!(matches!(store.get_user_points("nobody").await, Err(StoreError::UserNotFound(_))));
+}